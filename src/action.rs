@@ -4,8 +4,9 @@ use tile::*;
 #[derive(Debug,Copy,Clone,PartialEq,Eq)]
 pub enum Choice {
     DrawAndDiscard{riichi: bool},
-    Discard{figure:Figure, riichi: bool},
+    Discard{figure:Figure, red: bool, riichi: bool},
     Kong{figure:Figure},
+    North,
     NineTerminals,
     Mahjong
 }
@@ -17,18 +18,31 @@ impl Default for Choice {
 }
 
 impl Choice {
+    pub fn show(self) -> String {
+        match self {
+            Choice::DrawAndDiscard{riichi: false} => "Discard".to_string(),
+            Choice::DrawAndDiscard{riichi: true} => "Riichi".to_string(),
+            Choice::Discard{figure, red, riichi} =>
+                format!("{} {}{}", if riichi { "Riichi" } else { "Discard" }, if red { RedFives::MARK } else { "" }, figure.show()),
+            Choice::Kong{figure} => format!("Kong {}", figure.show()),
+            Choice::North => "North".to_string(),
+            Choice::NineTerminals => "NineTerminals".to_string(),
+            Choice::Mahjong => "Mahjong".to_string(),
+        }
+    }
     pub fn parse(s: &str) -> Result<Self, failure::Error> {
         let mut tokens = s.split_whitespace();
         let t = tokens.next()
             .ok_or(failure::err_msg(format!("No command")))?;
         match t {
+            "North" => Ok(Choice::North),
             "NineTerminals" => Ok(Choice::NineTerminals),
-            "Mohjong" => Ok(Choice::Mahjong),
+            "Mahjong" => Ok(Choice::Mahjong),
             "Discard" | "Riichi" => {
                 let riichi = t == "Riichi";
                 if let Some(expr) = tokens.next() {
-                    Figure::parse(expr).ok_or(failure::err_msg("Parse error"))
-                        .map(|figure| Choice::Discard{figure, riichi})
+                    RedFives::parse(expr).ok_or(failure::err_msg("Parse error"))
+                        .map(|(figure, red)| Choice::Discard{figure, red, riichi})
                 } else {
                     Ok(Choice::DrawAndDiscard{riichi})
                 }
@@ -62,6 +76,15 @@ impl Claim {
     pub fn id(self) -> usize {
         self.0 as usize
     }
+    pub fn show(self) -> &'static str {
+        match self {
+            Claim::MAHJONG => "Mahjong",
+            Claim::KONG => "Kong",
+            Claim::PUNG => "Pung",
+            Claim::CHOW => "Chow",
+            _ => "Through",
+        }
+    }
 }
 
 impl Default for Claim {
@@ -90,20 +113,22 @@ impl Claim {
 
 pub struct Claims {
     claims: u16,
-    claimee: Wind
+    claimee: Wind,
+    seats: usize
 }
 
 impl Claims {
-    pub fn new(claimee: Wind) -> Self {
+    pub fn new(claimee: Wind, seats: usize) -> Self {
         Claims {
             claims: 0,
-            claimee
+            claimee,
+            seats
         }
     }
 
     pub fn add(&mut self, claim: Claim, claimer: Wind) {
         assert_ne!(self.claimee, claimer);
-        let nth = (claimer.id() + 3 - self.claimee.id()) % 4;
+        let nth = (claimer.id() + self.seats - 1 - self.claimee.id()) % self.seats;
         if claim != Claim::THROUGH {
             self.claims |= 0o1 << (3 * claim.id() + nth);
         }
@@ -111,6 +136,9 @@ impl Claims {
     pub fn is_empty(&self) -> bool {
         self.claims == 0
     }
+    pub fn count(&self, claim: Claim) -> usize {
+        ((self.claims >> (3 * claim.id())) & 0o7).count_ones() as usize
+    }
     pub fn next(&mut self) -> Option<(Claim, Wind)> {
         if !self.is_empty() {
             let t = self.claims;
@@ -118,7 +146,7 @@ impl Claims {
             self.claims = t & (t-1);
             let claim = Claim::from_id(i / 3);
             let nth = i % 3;
-            let claimer = Wind::from_id(nth + 1);
+            let claimer = Wind::from_id((self.claimee.id() + nth + 1) % self.seats);
             Some((claim, claimer))
         } else {
             None
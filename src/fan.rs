@@ -0,0 +1,631 @@
+use tile::*;
+use hand::*;
+use yaku::*;
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Fan {
+    BigFourWinds,
+    BigThreeDragons,
+    AllGreen,
+    NineGates,
+    FourKongs,
+    SevenShiftedPairs,
+    ThirteenOrphans,
+    AllTerminals,
+    LittleFourWinds,
+    LittleThreeDragons,
+    AllHonors,
+    FourConcealedPungs,
+    PureTerminalChows,
+    QuadrupleChow,
+    FourPureShiftedPungs,
+    FourPureShiftedChows,
+    ThreeKongs,
+    AllTerminalsAndHonors,
+    SevenPairs,
+    GreaterHonorsAndKnittedTiles,
+    AllEvenPungs,
+    FullFlush,
+    PureTripleChow,
+    PureShiftedPungs,
+    UpperTiles,
+    MiddleTiles,
+    LowerTiles,
+    PureStraight,
+    ThreeSuitedTerminalChows,
+    PureShiftedChows,
+    AllFives,
+    TriplePung,
+    ThreeConcealedPungs,
+    LesserHonorsAndKnittedTiles,
+    KnittedStraight,
+    UpperFour,
+    LowerFour,
+    BigThreeWinds,
+    MixedStraight,
+    ReversibleTiles,
+    MixedTripleChow,
+    MixedShiftedPungs,
+    ChickenHand,
+    LastTileDraw,
+    LastTileClaim,
+    OutWithReplacementTile,
+    RobbingTheKong,
+    TwoConcealedKongs,
+    AllPungs,
+    HalfFlush,
+    MixedShiftedChows,
+    AllTypes,
+    MeldedHand,
+    TwoDragonPungs,
+    OutsideHand,
+    FullyConcealedHand,
+    TwoMeldedKongs,
+    LastTile,
+    DragonPung,
+    PrevalentWind,
+    SeatWind,
+    ConcealedHand,
+    AllChows,
+    TileHog,
+    DoublePung,
+    TwoConcealedPungs,
+    ConcealedKong,
+    AllSimples,
+    PureDoubleChow,
+    MixedDoubleChow,
+    ShortStraight,
+    TwoTerminalChows,
+    PungOfTerminalsOrHonors,
+    MeldedKong,
+    OneVoidedSuit,
+    NoHonors,
+    EdgeWait,
+    ClosedWait,
+    SingleWait,
+    SelfDrawn,
+    FlowerTiles,
+}
+
+// a win needs this much without the flowers
+pub const MINIMUM: usize = 8;
+
+impl Fan {
+    pub fn points(self) -> usize {
+        use self::Fan::*;
+        match self {
+            BigFourWinds | BigThreeDragons | AllGreen | NineGates | FourKongs | SevenShiftedPairs | ThirteenOrphans => 88,
+            AllTerminals | LittleFourWinds | LittleThreeDragons | AllHonors | FourConcealedPungs | PureTerminalChows => 64,
+            QuadrupleChow | FourPureShiftedPungs => 48,
+            FourPureShiftedChows | ThreeKongs | AllTerminalsAndHonors => 32,
+            SevenPairs | GreaterHonorsAndKnittedTiles | AllEvenPungs | FullFlush | PureTripleChow |
+            PureShiftedPungs | UpperTiles | MiddleTiles | LowerTiles => 24,
+            PureStraight | ThreeSuitedTerminalChows | PureShiftedChows | AllFives | TriplePung | ThreeConcealedPungs => 16,
+            LesserHonorsAndKnittedTiles | KnittedStraight | UpperFour | LowerFour | BigThreeWinds => 12,
+            MixedStraight | ReversibleTiles | MixedTripleChow | MixedShiftedPungs | ChickenHand | LastTileDraw |
+            LastTileClaim | OutWithReplacementTile | RobbingTheKong | TwoConcealedKongs => 8,
+            AllPungs | HalfFlush | MixedShiftedChows | AllTypes | MeldedHand | TwoDragonPungs => 6,
+            OutsideHand | FullyConcealedHand | TwoMeldedKongs | LastTile => 4,
+            DragonPung | PrevalentWind | SeatWind | ConcealedHand | AllChows | TileHog | DoublePung |
+            TwoConcealedPungs | ConcealedKong | AllSimples => 2,
+            _ => 1,
+        }
+    }
+    pub fn show(self) -> &'static str {
+        use self::Fan::*;
+        match self {
+            BigFourWinds => "大四喜",
+            BigThreeDragons => "大三元",
+            AllGreen => "绿一色",
+            NineGates => "九莲宝灯",
+            FourKongs => "四杠",
+            SevenShiftedPairs => "连七对",
+            ThirteenOrphans => "十三幺",
+            AllTerminals => "清幺九",
+            LittleFourWinds => "小四喜",
+            LittleThreeDragons => "小三元",
+            AllHonors => "字一色",
+            FourConcealedPungs => "四暗刻",
+            PureTerminalChows => "一色双龙会",
+            QuadrupleChow => "一色四同顺",
+            FourPureShiftedPungs => "一色四节高",
+            FourPureShiftedChows => "一色四步高",
+            ThreeKongs => "三杠",
+            AllTerminalsAndHonors => "混幺九",
+            SevenPairs => "七对",
+            GreaterHonorsAndKnittedTiles => "七星不靠",
+            AllEvenPungs => "全双刻",
+            FullFlush => "清一色",
+            PureTripleChow => "一色三同顺",
+            PureShiftedPungs => "一色三节高",
+            UpperTiles => "全大",
+            MiddleTiles => "全中",
+            LowerTiles => "全小",
+            PureStraight => "清龙",
+            ThreeSuitedTerminalChows => "三色双龙会",
+            PureShiftedChows => "一色三步高",
+            AllFives => "全带五",
+            TriplePung => "三同刻",
+            ThreeConcealedPungs => "三暗刻",
+            LesserHonorsAndKnittedTiles => "全不靠",
+            KnittedStraight => "组合龙",
+            UpperFour => "大于五",
+            LowerFour => "小于五",
+            BigThreeWinds => "三风刻",
+            MixedStraight => "花龙",
+            ReversibleTiles => "推不倒",
+            MixedTripleChow => "三色三同顺",
+            MixedShiftedPungs => "三色三节高",
+            ChickenHand => "无番和",
+            LastTileDraw => "妙手回春",
+            LastTileClaim => "海底捞月",
+            OutWithReplacementTile => "杠上开花",
+            RobbingTheKong => "抢杠和",
+            TwoConcealedKongs => "双暗杠",
+            AllPungs => "碰碰和",
+            HalfFlush => "混一色",
+            MixedShiftedChows => "三色三步高",
+            AllTypes => "五门齐",
+            MeldedHand => "全求人",
+            TwoDragonPungs => "双箭刻",
+            OutsideHand => "全带幺",
+            FullyConcealedHand => "不求人",
+            TwoMeldedKongs => "双明杠",
+            LastTile => "和绝张",
+            DragonPung => "箭刻",
+            PrevalentWind => "圈风刻",
+            SeatWind => "门风刻",
+            ConcealedHand => "门前清",
+            AllChows => "平和",
+            TileHog => "四归一",
+            DoublePung => "双同刻",
+            TwoConcealedPungs => "双暗刻",
+            ConcealedKong => "暗杠",
+            AllSimples => "断幺",
+            PureDoubleChow => "一般高",
+            MixedDoubleChow => "喜相逢",
+            ShortStraight => "连六",
+            TwoTerminalChows => "老少副",
+            PungOfTerminalsOrHonors => "幺九刻",
+            MeldedKong => "明杠",
+            OneVoidedSuit => "缺一门",
+            NoHonors => "无字",
+            EdgeWait => "边张",
+            ClosedWait => "坎张",
+            SingleWait => "单钓将",
+            SelfDrawn => "自摸",
+            FlowerTiles => "花牌",
+        }
+    }
+    // fans implied by this one, which are not counted again; the fans between chows are
+    // kept apart in chow_fans instead
+    pub fn excludes(self) -> &'static [Fan] {
+        use self::Fan::*;
+        match self {
+            BigFourWinds => &[BigThreeWinds, LittleFourWinds, AllPungs, SeatWind, PrevalentWind, PungOfTerminalsOrHonors],
+            BigThreeDragons => &[LittleThreeDragons, TwoDragonPungs, DragonPung],
+            AllGreen => &[HalfFlush],
+            NineGates => &[FullFlush, ConcealedHand, PungOfTerminalsOrHonors, NoHonors],
+            FourKongs => &[ThreeKongs, TwoConcealedKongs, TwoMeldedKongs, ConcealedKong, MeldedKong, AllPungs, SingleWait],
+            SevenShiftedPairs => &[SevenPairs, FullFlush, FullyConcealedHand, ConcealedHand, SingleWait, NoHonors],
+            ThirteenOrphans => &[AllTerminalsAndHonors, AllTypes, FullyConcealedHand, ConcealedHand, SingleWait],
+            AllTerminals => &[AllTerminalsAndHonors, AllPungs, OutsideHand, DoublePung, PungOfTerminalsOrHonors, NoHonors],
+            LittleFourWinds => &[BigThreeWinds, PungOfTerminalsOrHonors],
+            LittleThreeDragons => &[TwoDragonPungs, DragonPung],
+            AllHonors => &[AllTerminalsAndHonors, AllPungs, OutsideHand, PungOfTerminalsOrHonors],
+            FourConcealedPungs => &[ThreeConcealedPungs, TwoConcealedPungs, AllPungs, ConcealedHand],
+            PureTerminalChows => &[SevenPairs, FullFlush, AllChows, NoHonors],
+            QuadrupleChow => &[TileHog],
+            FourPureShiftedPungs => &[PureShiftedPungs, AllPungs],
+            ThreeKongs => &[TwoConcealedKongs, TwoMeldedKongs, ConcealedKong, MeldedKong],
+            AllTerminalsAndHonors => &[AllPungs, OutsideHand, PungOfTerminalsOrHonors],
+            SevenPairs => &[FullyConcealedHand, ConcealedHand, SingleWait],
+            GreaterHonorsAndKnittedTiles => &[LesserHonorsAndKnittedTiles, AllTypes, FullyConcealedHand, ConcealedHand, SingleWait],
+            AllEvenPungs => &[AllPungs, AllSimples, NoHonors],
+            FullFlush => &[NoHonors],
+            UpperTiles => &[UpperFour, NoHonors],
+            MiddleTiles => &[AllSimples, NoHonors],
+            LowerTiles => &[LowerFour, NoHonors],
+            ThreeSuitedTerminalChows => &[AllChows, NoHonors],
+            AllFives => &[AllSimples, NoHonors],
+            TriplePung => &[DoublePung],
+            ThreeConcealedPungs => &[TwoConcealedPungs],
+            LesserHonorsAndKnittedTiles => &[AllTypes, FullyConcealedHand, ConcealedHand, SingleWait],
+            UpperFour | LowerFour => &[NoHonors],
+            ReversibleTiles => &[OneVoidedSuit],
+            LastTileDraw | OutWithReplacementTile => &[SelfDrawn],
+            RobbingTheKong => &[LastTile],
+            TwoConcealedKongs => &[ConcealedKong],
+            MeldedHand => &[SingleWait],
+            TwoDragonPungs => &[DragonPung],
+            FullyConcealedHand => &[ConcealedHand, SelfDrawn],
+            TwoMeldedKongs => &[MeldedKong],
+            AllChows | AllSimples => &[NoHonors],
+            _ => &[],
+        }
+    }
+}
+
+fn four_chows(c: &[Figure], pair: Option<Figure>) -> Option<Fan> {
+    let r: Vec<usize> = c.iter().map(|f| f.rank().id()).collect();
+    let five = |suit: Suit| pair == Some(Figure::from_suitrank(suit, Rank::from_id(4)));
+    if c.iter().all(|f| f.suit() == c[0].suit()) {
+        let d = r[1] - r[0];
+        if d == 0 && r[3] == r[0] {
+            return Some(Fan::QuadrupleChow)
+        }
+        if (d == 1 || d == 2) && r[2] - r[1] == d && r[3] - r[2] == d {
+            return Some(Fan::FourPureShiftedChows)
+        }
+        if r == [0, 0, 6, 6] && five(c[0].suit()) {
+            return Some(Fan::PureTerminalChows)
+        }
+    }
+    let terminals = |suit: Suit| [0, 6].iter().all(|&r| c.contains(&Figure::from_suitrank(suit, Rank::from_id(r))));
+    let suits: Vec<Suit> = Suit::mk_iter().filter(|s| s.is_numeric()).collect();
+    (0..3).find(|&i| five(suits[i]) && (0..3).filter(|&j| j != i).all(|j| terminals(suits[j])))
+        .map(|_| Fan::ThreeSuitedTerminalChows)
+}
+
+fn three_chows(a: Figure, b: Figure, c: Figure) -> Option<Fan> {
+    let mut r = [a.rank().id(), b.rank().id(), c.rank().id()];
+    r.sort();
+    let d = r[1] - r[0];
+    if a.suit() == b.suit() && b.suit() == c.suit() {
+        match r {
+            _ if d == 0 && r[2] == r[0] => Some(Fan::PureTripleChow),
+            [0, 3, 6] => Some(Fan::PureStraight),
+            _ if (d == 1 || d == 2) && r[2] - r[1] == d => Some(Fan::PureShiftedChows),
+            _ => None
+        }
+    } else if a.suit() != b.suit() && b.suit() != c.suit() && a.suit() != c.suit() {
+        match r {
+            _ if d == 0 && r[2] == r[0] => Some(Fan::MixedTripleChow),
+            [0, 3, 6] => Some(Fan::MixedStraight),
+            _ if d == 1 && r[2] - r[1] == 1 => Some(Fan::MixedShiftedChows),
+            _ => None
+        }
+    } else {
+        None
+    }
+}
+
+fn two_chows(a: Figure, b: Figure) -> Option<Fan> {
+    let (ra, rb) = (a.rank().id(), b.rank().id());
+    if a.suit() != b.suit() {
+        return if ra == rb { Some(Fan::MixedDoubleChow) } else { None }
+    }
+    match (std::cmp::min(ra, rb), std::cmp::max(ra, rb)) {
+        (lo, hi) if lo == hi => Some(Fan::PureDoubleChow),
+        (0, 6) => Some(Fan::TwoTerminalChows),
+        (lo, hi) if hi - lo == 3 => Some(Fan::ShortStraight),
+        _ => None
+    }
+}
+
+fn sum(fans: &[Fan]) -> usize {
+    fans.iter().map(|f| f.points()).sum()
+}
+
+// a fan between chows has to bring in a chow no other such fan used yet,
+// so the chows combine at most as a tree
+fn chow_fans(chows: &[Figure], pair: Option<Figure>) -> Vec<Fan> {
+    let n = chows.len();
+    if n == 4 {
+        if let Some(fan) = four_chows(chows, pair) {
+            return vec![fan]
+        }
+    }
+    let mut best = Vec::new();
+    for i in 0..n {
+        for j in i+1..n {
+            for k in j+1..n {
+                let fan = match three_chows(chows[i], chows[j], chows[k]) {
+                    Some(fan) => fan,
+                    None => continue
+                };
+                let mut fans = vec![fan];
+                if let Some(l) = (0..n).find(|&l| l != i && l != j && l != k) {
+                    if let Some(more) = [i, j, k].iter().filter_map(|&x| two_chows(chows[l], chows[x])).max_by_key(|f| f.points()) {
+                        fans.push(more);
+                    }
+                }
+                if sum(&fans) > sum(&best) {
+                    best = fans;
+                }
+            }
+        }
+    }
+    if !best.is_empty() {
+        return best
+    }
+    let mut edges = Vec::new();
+    for i in 0..n {
+        for j in i+1..n {
+            if let Some(fan) = two_chows(chows[i], chows[j]) {
+                edges.push((i, j, fan));
+            }
+        }
+    }
+    for mask in 0..1usize << edges.len() {
+        let mut root = [0, 1, 2, 3];
+        let mut fans = Vec::new();
+        for (e, &(i, j, fan)) in edges.iter().enumerate().filter(|&(e, _)| mask >> e & 1 != 0) {
+            let (ri, rj) = (find(&root, i), find(&root, j));
+            if ri == rj {
+                fans.clear();
+                break
+            }
+            root[ri] = rj;
+            fans.push(fan);
+            if e + 1 == edges.len() || mask >> (e + 1) == 0 {
+                break
+            }
+        }
+        if sum(&fans) > sum(&best) {
+            best = fans;
+        }
+    }
+    best
+}
+
+fn find(root: &[usize; 4], mut i: usize) -> usize {
+    while root[i] != i {
+        i = root[i];
+    }
+    i
+}
+
+// unique_wait: the winning figure is the only one the hand was waiting for
+pub fn evaluate(d: &Decomposition, ctx: &Context, unique_wait: bool) -> Vec<(Fan, usize)> {
+    let figures = d.figures();
+    let standard = d.form == Form::Standard;
+    let pair = match d.form {
+        Form::Standard | Form::KnittedStraight => d.pairs.first().cloned(),
+        _ => None
+    };
+    let mut chows: Vec<Figure> = d.sets().filter(|s| s.shape() == Shape::CHOW).map(|s| s.figure()).collect();
+    let mut pungs: Vec<Figure> = d.sets().filter(|s| s.shape() != Shape::CHOW).map(|s| s.figure()).collect();
+    chows.sort_by_key(|f| f.id());
+    pungs.sort_by_key(|f| f.id());
+    let numbers: Vec<Figure> = pungs.iter().cloned().filter(|f| f.suit().is_numeric()).collect();
+    let kongs = |concealed: bool| d.groups.iter().filter(|g| g.set.shape() >= Shape::KONG && g.concealed == concealed).count();
+    let (concealed_kongs, melded_kongs) = (kongs(true), kongs(false));
+    let concealed_pungs = (0..d.groups.len())
+        .filter(|&i| d.groups[i].set.shape() != Shape::CHOW && d.is_concealed_set(i, ctx.tsumo))
+        .count();
+    let all = |f: &dyn Fn(Figure) -> bool| Figure::mk_iter().all(|x| figures.get(x) == 0 || f(x));
+    let has = |f: &dyn Fn(Figure) -> bool| Figure::mk_iter().any(|x| figures.get(x) > 0 && f(x));
+    let honors = has(&|f| f.is_honor());
+    let suits = Suit::mk_iter().filter(|&s| s.is_numeric() && !figures.ranks(s).is_empty()).count();
+    let ranked = |lo: usize, hi: usize| all(&|f| f.suit().is_numeric() && f.rank().id() >= lo && f.rank().id() <= hi);
+    let winds = pungs.iter().filter(|f| f.is_wind()).count();
+    let dragons = pungs.iter().filter(|f| f.is_dragon()).count();
+
+    let mut v: Vec<(Fan, usize)> = Vec::new();
+    {
+        let mut add = |fan: Fan, n: usize| match v.iter_mut().find(|&&mut (f, _)| f == fan) {
+            Some(entry) => entry.1 += n,
+            None => if n > 0 { v.push((fan, n)) }
+        };
+        match d.form {
+            Form::ThirteenOrphans => add(Fan::ThirteenOrphans, 1),
+            Form::SevenPairs => {
+                let shifted = suits == 1 && !honors && (0..3).any(|lo| {
+                    let suit = d.pairs[0].suit();
+                    (lo..lo + 7).all(|r| figures.get(Figure::from_suitrank(suit, Rank::from_id(r))) == 2)
+                });
+                add(if shifted { Fan::SevenShiftedPairs } else { Fan::SevenPairs }, 1)
+            },
+            Form::Knitted => {
+                let greater = Figure::mk_iter().filter(|f| f.is_honor() && figures.get(*f) > 0).count() == 7;
+                add(if greater { Fan::GreaterHonorsAndKnittedTiles } else { Fan::LesserHonorsAndKnittedTiles }, 1);
+                add(Fan::KnittedStraight, knitted_straights().iter().any(|k| Figure::mk_iter().all(|f| figures.get(f) >= k.get(f))) as usize);
+            },
+            Form::KnittedStraight => add(Fan::KnittedStraight, 1),
+            Form::Standard => {}
+        }
+
+        for fan in chow_fans(&chows, pair) {
+            add(fan, 1);
+        }
+        let consecutive = |fs: &[Figure]| fs.windows(2).all(|w| w[1].rank().id() == w[0].rank().id() + 1);
+        let same_suit = |fs: &[Figure]| fs.iter().all(|f| f.suit() == fs[0].suit());
+        if numbers.len() == 4 && same_suit(&numbers) && consecutive(&numbers) {
+            add(Fan::FourPureShiftedPungs, 1);
+        }
+        let mut triples = Vec::new();
+        for i in 0..numbers.len() {
+            for j in i+1..numbers.len() {
+                for k in j+1..numbers.len() {
+                    triples.push([numbers[i], numbers[j], numbers[k]]);
+                }
+            }
+        }
+        let distinct = |t: &[Figure; 3]| t[0].suit() != t[1].suit() && t[1].suit() != t[2].suit() && t[0].suit() != t[2].suit();
+        for t in triples.iter_mut() {
+            t.sort_by_key(|f| f.rank().id());
+        }
+        add(Fan::PureShiftedPungs, triples.iter().any(|t| same_suit(t) && consecutive(t)) as usize);
+        add(Fan::MixedShiftedPungs, triples.iter().any(|t| distinct(t) && consecutive(t)) as usize);
+        add(Fan::TriplePung, triples.iter().any(|t| distinct(t) && t[0].rank() == t[2].rank()) as usize);
+        let mut doubles = 0;
+        for i in 0..numbers.len() {
+            for j in i+1..numbers.len() {
+                if numbers[i].rank() == numbers[j].rank() {
+                    doubles += 1;
+                }
+            }
+        }
+        add(Fan::DoublePung, doubles);
+
+        let pair_is = |f: &dyn Fn(&Figure) -> bool| pair.as_ref().is_some_and(f);
+        match winds {
+            4 => add(Fan::BigFourWinds, 1),
+            3 if pair_is(&Figure::is_wind) => add(Fan::LittleFourWinds, 1),
+            3 => add(Fan::BigThreeWinds, 1),
+            _ => {}
+        }
+        match dragons {
+            3 => add(Fan::BigThreeDragons, 1),
+            2 if pair_is(&Figure::is_dragon) => add(Fan::LittleThreeDragons, 1),
+            2 => add(Fan::TwoDragonPungs, 1),
+            n => add(Fan::DragonPung, n),
+        }
+        add(Fan::PrevalentWind, pungs.contains(&ctx.round.figure()) as usize);
+        add(Fan::SeatWind, pungs.contains(&ctx.seat.figure()) as usize);
+        let plain_winds = if winds < 3 {
+            pungs.iter().filter(|&&f| f.is_wind() && f != ctx.seat.figure() && f != ctx.round.figure()).count()
+        } else {
+            0
+        };
+        add(Fan::PungOfTerminalsOrHonors, pungs.iter().filter(|f| f.is_terminal()).count() + plain_winds);
+
+        if d.is_closed() && d.groups.iter().all(|g| g.set.shape() < Shape::KONG) && suits == 1 && !honors {
+            let nine_gates = Ranks::from_raw(0o311111113);
+            let ranks = figures.ranks(d.winning.suit());
+            if ranks.contains(Ranks::make_one(d.winning.rank())) && ranks.sub(Ranks::make_one(d.winning.rank())) == nine_gates {
+                add(Fan::NineGates, 1);
+            }
+        }
+        match concealed_kongs + melded_kongs {
+            4 => add(Fan::FourKongs, 1),
+            3 => add(Fan::ThreeKongs, 1),
+            _ => {
+                add(if concealed_kongs == 2 { Fan::TwoConcealedKongs } else { Fan::ConcealedKong }, (concealed_kongs > 0) as usize);
+                add(if melded_kongs == 2 { Fan::TwoMeldedKongs } else { Fan::MeldedKong }, (melded_kongs > 0) as usize);
+            }
+        }
+        match concealed_pungs {
+            4 => add(Fan::FourConcealedPungs, 1),
+            3 => add(Fan::ThreeConcealedPungs, 1),
+            2 => add(Fan::TwoConcealedPungs, 1),
+            _ => {}
+        }
+        if standard && chows.is_empty() {
+            add(Fan::AllPungs, 1);
+            add(Fan::AllEvenPungs, all(&|f| f.suit().is_numeric() && f.rank().id() % 2 == 1) as usize);
+        }
+
+        let green = Figures::parse_mpsz("23468s6z").expect("green figures");
+        let reversible = Figures::parse_mpsz("1234589p245689s7z").expect("reversible figures");
+        add(Fan::AllGreen, all(&|f| green.get(f) > 0) as usize);
+        add(Fan::ReversibleTiles, all(&|f| reversible.get(f) > 0) as usize);
+        if all(&|f| f.is_terminal()) {
+            add(Fan::AllTerminals, 1);
+        } else if all(&|f| f.is_honor()) {
+            add(Fan::AllHonors, 1);
+        } else if all(&|f| f.is_orphan()) {
+            add(Fan::AllTerminalsAndHonors, 1);
+        }
+        match suits {
+            1 if honors => add(Fan::HalfFlush, 1),
+            1 => add(Fan::FullFlush, 1),
+            2 => add(Fan::OneVoidedSuit, 1),
+            3 if has(&|f| f.is_wind()) && has(&|f| f.is_dragon()) => add(Fan::AllTypes, 1),
+            _ => {}
+        }
+        add(Fan::UpperTiles, ranked(6, 8) as usize);
+        add(Fan::MiddleTiles, ranked(3, 5) as usize);
+        add(Fan::LowerTiles, ranked(0, 2) as usize);
+        add(Fan::UpperFour, ranked(5, 8) as usize);
+        add(Fan::LowerFour, ranked(0, 3) as usize);
+        add(Fan::AllSimples, all(&|f| !f.is_orphan()) as usize);
+        add(Fan::NoHonors, !honors as usize);
+
+        let is_five = |f: Figure| f.suit().is_numeric() && f.rank().id() == 4;
+        if standard && pair.is_some_and(is_five) && d.sets().all(|s| s.ranks().has(Rank::from_id(4)) && s.figure().suit().is_numeric()) {
+            add(Fan::AllFives, 1);
+        }
+        if standard && pair.is_some_and(|p| p.is_orphan()) && d.sets().all(|s| s.has_orphan()) {
+            add(Fan::OutsideHand, 1);
+        }
+        let chowed = (standard && chows.len() == 4) || (d.form == Form::KnittedStraight && chows.len() == 1);
+        add(Fan::AllChows, (chowed && !honors) as usize);
+        add(Fan::TileHog, Figure::mk_iter()
+            .filter(|&f| figures.get(f) == 4 && !d.sets().any(|s| s.shape() >= Shape::KONG && s.figure() == f))
+            .count());
+        if standard && d.groups.iter().all(|g| !g.concealed) && !ctx.tsumo && d.wait == Wait::Tanki {
+            add(Fan::MeldedHand, 1);
+        }
+        if d.is_closed() {
+            add(if ctx.tsumo { Fan::FullyConcealedHand } else { Fan::ConcealedHand }, 1);
+        }
+        if unique_wait {
+            match d.wait {
+                Wait::Penchan => add(Fan::EdgeWait, 1),
+                Wait::Kanchan => add(Fan::ClosedWait, 1),
+                Wait::Tanki => add(Fan::SingleWait, 1),
+                _ => {}
+            }
+        }
+        if ctx.last_tile {
+            add(if ctx.tsumo { Fan::LastTileDraw } else { Fan::LastTileClaim }, 1);
+        }
+        add(Fan::OutWithReplacementTile, ctx.rinshan as usize);
+        add(Fan::RobbingTheKong, ctx.chankan as usize);
+        add(Fan::LastTile, ctx.last_copy as usize);
+        add(Fan::SelfDrawn, ctx.tsumo as usize);
+        add(Fan::FlowerTiles, ctx.flowers);
+    }
+    // a fan left out does not exclude any other, so the larger ones go first
+    let mut order: Vec<Fan> = v.iter().map(|&(fan, _)| fan).collect();
+    order.sort_by_key(|f| std::cmp::Reverse(f.points()));
+    let mut kept: Vec<Fan> = Vec::new();
+    for fan in order {
+        if !kept.iter().any(|k| k.excludes().contains(&fan)) {
+            kept.push(fan);
+        }
+    }
+    v.retain(|&(fan, _)| kept.contains(&fan));
+    if v.iter().all(|&(fan, _)| fan == Fan::FlowerTiles) {
+        v.insert(0, (Fan::ChickenHand, 1));
+    }
+    v
+}
+
+pub fn points(fans: &[(Fan, usize)]) -> usize {
+    fans.iter().map(|&(fan, n)| fan.points() * n).sum()
+}
+
+pub fn is_enough(fans: &[(Fan, usize)]) -> bool {
+    fans.iter().filter(|&&(fan, _)| fan != Fan::FlowerTiles).map(|&(fan, n)| fan.points() * n).sum::<usize>() >= MINIMUM
+}
+
+pub fn best(hand: &Hand, winning: Figure, ctx: &Context) -> Option<Vec<(Fan, usize)>> {
+    let waits = Figure::mk_iter()
+        .filter(|&f| hand.all_figures(f).get(f) <= 4 && !hand.decompose_mcr(f).is_empty())
+        .count();
+    hand.decompose_mcr(winning).iter()
+        .map(|d| evaluate(d, ctx, waits == 1))
+        .max_by_key(|fans| points(fans))
+}
+
+#[test]
+fn fan_test() {
+    use table::Wind;
+    let ron = Context::new(Wind::SOUTH, Wind::EAST);
+    let tsumo = Context { tsumo: true, ..ron };
+    let score = |h: &str, w: &str, ctx: &Context| best(&Hand::parse_mpsz(h), Figure::parse_mpsz(w), ctx).unwrap();
+    let fans = score("123456789m123p5s", "5s", &ron);
+    assert!(fans.contains(&(Fan::PureStraight, 1)));
+    assert!(fans.contains(&(Fan::SingleWait, 1)));
+    assert!(fans.contains(&(Fan::ConcealedHand, 1)));
+    assert!(!fans.contains(&(Fan::ShortStraight, 1)));
+    assert_eq!(score("1112345678999m", "5m", &ron), vec![(Fan::NineGates, 1), (Fan::TwoConcealedPungs, 1)]);
+    assert_eq!(score("1122334455667m", "7m", &tsumo), vec![(Fan::SevenShiftedPairs, 1), (Fan::SelfDrawn, 1)]);
+    let fans = score("147m258s369p1234z", "5z", &ron);
+    assert_eq!(fans, vec![(Fan::LesserHonorsAndKnittedTiles, 1), (Fan::KnittedStraight, 1)]);
+    assert_eq!(points(&score("147m258s369p2255z", "5z", &ron)), 12 + 6 + 2 + 2);
+    let fans = score("123m123p123s789s5p", "5p", &ron);
+    assert!(fans.contains(&(Fan::MixedTripleChow, 1)));
+    assert!(fans.contains(&(Fan::TwoTerminalChows, 1)));
+    let meld = |shape, f: &str| Group { set: Set::from_shape_figure(shape, Figure::parse_mpsz(f)), concealed: false };
+    let open = Hand { melds: vec![meld(Shape::CHOW, "4p")], ..Hand::parse_mpsz("123m123m456p7z") };
+    let fans = best(&open, Figure::parse_mpsz("7z"), &ron).unwrap();
+    assert!(fans.contains(&(Fan::PureDoubleChow, 2)));
+    assert!(fans.contains(&(Fan::SingleWait, 1)));
+    assert!(!is_enough(&score("234m345p567s2288p", "8p", &ron)));
+    let chicken = Hand { melds: vec![meld(Shape::CHOW, "2m"), meld(Shape::CHOW, "5p"), meld(Shape::PUNG, "8s")], ..Hand::parse_mpsz("78m11z") };
+    assert_eq!(best(&chicken, Figure::parse_mpsz("6m"), &Context { flowers: 2, ..ron }).unwrap(), vec![(Fan::ChickenHand, 1), (Fan::FlowerTiles, 2)]);
+}
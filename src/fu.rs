@@ -0,0 +1,78 @@
+use tile::*;
+use hand::*;
+use yaku::*;
+
+pub fn is_pinfu_shape(d: &Decomposition, ctx: &Context) -> bool {
+    d.form == Form::Standard && d.is_closed() && d.wait == Wait::Ryanmen
+        && d.sets().all(|s| s.shape() == Shape::CHOW) && !ctx.is_value(d.pairs[0])
+}
+
+pub fn round_up(fu: usize) -> usize {
+    fu.div_ceil(10) * 10
+}
+
+pub fn fu(d: &Decomposition, ctx: &Context) -> usize {
+    match d.form {
+        Form::SevenPairs => return 25,
+        Form::ThirteenOrphans => return 30,
+        _ => {}
+    }
+    if is_pinfu_shape(d, ctx) {
+        return if ctx.tsumo { 20 } else { 30 }
+    }
+    let mut fu = 20;
+    if d.is_closed() && !ctx.tsumo {
+        fu += 10;
+    }
+    if ctx.tsumo {
+        fu += 2;
+    }
+    let pair = d.pairs[0];
+    if pair.is_dragon() {
+        fu += 2;
+    }
+    if pair == ctx.seat.figure() {
+        fu += 2;
+    }
+    if pair == ctx.round.figure() {
+        fu += 2;
+    }
+    for (i, g) in d.groups.iter().enumerate() {
+        let shape = g.set.shape();
+        if shape == Shape::CHOW {
+            continue
+        }
+        let mut f = 2;
+        if g.set.figure().is_orphan() {
+            f *= 2;
+        }
+        if d.is_concealed_set(i, ctx.tsumo) {
+            f *= 2;
+        }
+        if shape >= Shape::KONG {
+            f *= 4;
+        }
+        fu += f;
+    }
+    match d.wait {
+        Wait::Kanchan | Wait::Penchan | Wait::Tanki => fu += 2,
+        Wait::Ryanmen | Wait::Shanpon => {}
+    }
+    if fu == 20 {
+        30
+    } else {
+        round_up(fu)
+    }
+}
+
+#[test]
+fn fu_test() {
+    let ron = Context::new(::table::Wind::SOUTH, ::table::Wind::EAST);
+    let tsumo = Context { tsumo: true, ..ron };
+    let best = |h: &str, w: &str, ctx: &Context| Hand::parse_mpsz(h).decompose(Figure::parse_mpsz(w)).iter().map(|d| fu(d, ctx)).max().unwrap();
+    assert_eq!(best("234567m23455p23s", "4s", &ron), 30);
+    assert_eq!(best("234567m23455p23s", "4s", &tsumo), 20);
+    assert_eq!(best("1133m5577p99s116z", "6z", &ron), 25);
+    assert_eq!(best("111m999p11s11z222z", "1s", &ron), 60);
+    assert_eq!(best("123m456p789s1133z", "3z", &tsumo), 40);
+}
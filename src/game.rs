@@ -4,22 +4,29 @@ use meld::*;
 use action::*;
 use player::*;
 use dice::*;
+use hand::*;
+use shanten;
+use yaku::*;
+use points::*;
+use points;
+use fan;
+use rule::*;
 use failure;
 use std;
 use std::io::{BufRead, Write};
 
 
 pub struct Sticks {
-    pub score: [isize; 4],
+    pub score: Vec<isize>,
     pub burst_flags: u8,
     pub deposit: isize,
     pub stack: isize
 }
 
 impl Sticks {
-    pub fn new() -> Self {
+    pub fn new(rules: &RuleSet) -> Self {
         Sticks {
-            score: [25000; 4],
+            score: vec![rules.starting_points; rules.seats],
             burst_flags: 0,
             deposit: 0,
             stack: 0
@@ -29,18 +36,41 @@ impl Sticks {
         self.score[payer] -=  points;
         self.score[receiver] += points;
     }
+    pub fn top(&self) -> usize {
+        (0..self.score.len()).max_by_key(|&i| (self.score[i], -(i as isize))).unwrap_or(0)
+    }
     pub fn is_bursted(&self) -> bool {
         self.burst_flags != 0
     }
+    // riichi sticks nobody won by the end of the game go to the top
+    pub fn award_deposit(&mut self) {
+        let top = self.top();
+        self.score[top] += self.deposit;
+        self.deposit = 0;
+    }
+}
+
+#[derive(Debug,Copy,Clone,Default,PartialEq,Eq)]
+pub struct Status {
+    pub riichi: bool,
+    pub double_riichi: bool,
+    pub ippatsu: bool,
+    pub temporary_furiten: bool,
+    pub riichi_furiten: bool,
+    pub pao: Option<(Wind, Yaku)>,
 }
 
 pub struct State<'a> {
+    status: [Status; 4],
+    rinshan: bool,
+    chankan: bool,
     table: &'a mut Table,
     sticks: &'a mut Sticks,
     round: Wind,
     dice: Dice,
     dealer: usize,
-    players: &'a mut [Player; 4]
+    players: &'a mut [Player],
+    rules: &'a RuleSet
 }
 
 #[derive(Debug,Copy,Clone,PartialEq,Eq)]
@@ -50,6 +80,7 @@ pub enum Phase {
     Replace{wind: Wind, expose: bool},
     Ask,
     AskRobbingKong{index: usize},
+    North{wind: Wind},
     Meld{wind: Wind, claim: Claim},
     Discard{wind: Wind},
 }
@@ -57,8 +88,8 @@ pub enum Phase {
 #[derive(Debug,Copy,Clone,PartialEq,Eq)]
 pub enum Finish {
     WinByDraw(Wind, Tile),
-    WinByDiscard(Wind),
-    ExaustiveDraw,
+    WinByDiscard(Winds),
+    ExaustiveDraw{tenpai: Winds, nagashi: Winds},
     FourRiichiAbort,
     NineTerminalAbort,
     FourWindAbort,
@@ -66,73 +97,47 @@ pub enum Finish {
     FourKongAbort,   
 }
 
-pub struct Players(pub [Player; 4]);
+pub struct Players(pub Vec<Player>);
 
 impl Players {
-    pub fn run_match(&mut self) -> Result<[isize; 4], failure::Error> {
-        let sticks = &mut Sticks::new();
-        for round in Wind::make_iter() {
-            self.run_round(sticks, round)?;
-            if sticks.is_bursted() {
-                break;
-            }
-        }
-        Ok(sticks.score)
+    pub fn run(&mut self, rules: &RuleSet) -> Result<Vec<isize>, failure::Error> {
+        self.run_rounds(rules, rules.length.rounds())
     }
 
-    pub fn run_halfmatch(&mut self) -> Result<[isize; 4], failure::Error> {
-        let sticks = &mut Sticks::new();
-        for round in Wind::make_iter().take(Wind::N/2) {
-            self.run_round(sticks, round)?;
-            if sticks.is_bursted() {
-                break;
-            }
-        }
-        Ok(sticks.score)
-    }
-
-    pub fn run_eastmatch(&mut self) -> Result<[isize; 4], failure::Error> {
-        let sticks = &mut Sticks::new();
-        let round = Wind::from_id(0);
-        self.run_round(sticks, round)?;
-        Ok(sticks.score)
-    }
-
-    pub fn run_round(&mut self, sticks: &mut Sticks, round: Wind) -> Result<(), failure::Error> {
-        for dealer in 0..4 {
-            self.run_hands(sticks, round, dealer)?;
-            if sticks.is_bursted() {
-                break;
+    pub fn run_rounds(&mut self, rules: &RuleSet, rounds: usize) -> Result<Vec<isize>, failure::Error> {
+        let sticks = &mut Sticks::new(rules);
+        let table = &mut Table::new(rules);
+        let (mut round, mut dealer) = (0, 0);
+        loop {
+            let finish = self.run_hand(rules, sticks, Wind::from_id(round), dealer, table)?;
+            match next_hand(rules, sticks, finish, round, dealer, rounds) {
+                Some((next_round, next_dealer, stack)) => {
+                    round = next_round;
+                    dealer = next_dealer;
+                    sticks.stack = stack;
+                },
+                None => break
             }
         }
-        Ok(())
+        sticks.award_deposit();
+        Ok(sticks.score.clone())
     }
 
-    pub fn run_hands(&mut self, sticks: &mut Sticks, round: Wind, dealer: usize) -> Result<(), failure::Error> {
-        let table = &mut Table::new();
-        loop {
-            let bonus_hand = self.run_hand(sticks, round, dealer, table)?;
-            if sticks.is_bursted() {
-                break;
-            }
-            if !bonus_hand {
-                break;
+    pub fn run_hand(&mut self, rules: &RuleSet, sticks: &mut Sticks, round: Wind, dealer: usize, table: &mut Table) -> Result<Finish, failure::Error> {
+        let dice = init_table(table, rules.seats);
+        if rules.style == Style::Mcr {
+            for wind in Wind::make_seats(rules.seats) {
+                table.replace_flowers(wind);
             }
         }
-        Ok(())
-    }
-    pub fn run_hand(&mut self, sticks: &mut Sticks, round: Wind, dealer: usize, table: &mut Table) -> Result<bool, failure::Error> {
-        let dice = init_table(table);
         let mut phase = Phase::new();
-        let players = &mut self.0;
-        let state = &mut State {
-            dice,
-            sticks,
-            round,
-            dealer,
-            table,
-            players,
+        let state = &mut State::new(rules, sticks, round, dealer, table, &mut self.0, dice);
+        let message = if rules.style == Style::Mcr {
+            format!("{}{}局", round.show(), dealer + 1)
+        } else {
+            format!("{}{}局 {}本場 供託{}", round.show(), dealer + 1, state.sticks.stack, state.sticks.deposit)
         };
+        state.broadcast(&message);
         let finish = loop {
             match phase.step(state)? {
                 Step::Phase(next_phase) => phase = next_phase,
@@ -140,11 +145,41 @@ impl Players {
             }
         };
         finish.payment(state);
-        Ok(finish.has_bonus_hand())
+        Ok(finish)
     }
 }
 
-pub fn init_table(table: &mut Table) -> Dice {
+// the round, dealer and honba of the hand after `finish`, or None once the game is over
+pub fn next_hand(rules: &RuleSet, sticks: &Sticks, finish: Finish, round: usize, dealer: usize, rounds: usize) -> Option<(usize, usize, isize)> {
+    let renchan = finish.has_bonus_hand() && rules.style == Style::Riichi;
+    let stack = if renchan || finish.is_goulash_hand() { sticks.stack + 1 } else { 0 };
+    if rules.busting && sticks.is_bursted() {
+        return None
+    }
+    let (mut round, mut dealer) = (round, dealer);
+    if renchan {
+        let all_last = round + 1 >= rounds && dealer == rules.seats - 1;
+        if all_last && rules.agari_yame && !finish.is_abortive()
+            && sticks.top() == dealer && sticks.score[dealer] >= rules.target_points {
+            return None
+        }
+    } else {
+        dealer += 1;
+        if dealer == rules.seats {
+            dealer = 0;
+            round += 1;
+        }
+    }
+    if round >= rounds {
+        let reached = sticks.score.iter().any(|&s| s >= rules.target_points);
+        if reached || !rules.extension || round > rounds || round >= Wind::N {
+            return None
+        }
+    }
+    Some((round, dealer, stack))
+}
+
+pub fn init_table(table: &mut Table, seats: usize) -> Dice {
     table.shuffle_tiles();
     let dice = Dice::new();
     table.break_tiles(dice.sum());
@@ -155,13 +190,13 @@ pub fn init_table(table: &mut Table) -> Dice {
     };
  
     for _ in 0..3 {
-        for wind in Wind::make_iter() {
+        for wind in Wind::make_seats(seats) {
             for _ in 0..4 {
                 draw(wind.id())
             }
         }
     }
-    for wind in Wind::make_iter() {
+    for wind in Wind::make_seats(seats) {
         draw(wind.id())
     }
     dice
@@ -170,24 +205,61 @@ pub fn init_table(table: &mut Table) -> Dice {
 impl Finish {
     pub fn is_goulash_hand(self) -> bool {
         match self {
-            Finish::ExaustiveDraw | Finish::FourRiichiAbort |
+            Finish::ExaustiveDraw{..} | Finish::FourRiichiAbort |
             Finish::NineTerminalAbort | Finish::FourWindAbort |
             Finish::ThreeWinAbort | Finish::FourKongAbort => true,
             _ => false
         }
     }
+    pub fn is_abortive(self) -> bool {
+        match self {
+            Finish::ExaustiveDraw{..} => false,
+            _ => self.is_goulash_hand()
+        }
+    }
     pub fn is_dealer_win(self) -> bool {
         use self::Finish::*;
         match self {
-            WinByDraw(Wind::EAST, ..) | WinByDiscard(Wind::EAST, ..) => true,
+            WinByDraw(Wind::EAST, ..) => true,
+            WinByDiscard(winners) => winners.contains(Wind::EAST),
             _ => false
         }
     }
+    pub fn show(self) -> &'static str {
+        match self {
+            Finish::ExaustiveDraw{..} => "流局",
+            Finish::FourRiichiAbort => "四家立直",
+            Finish::NineTerminalAbort => "九種九牌",
+            Finish::FourWindAbort => "四風連打",
+            Finish::ThreeWinAbort => "三家和",
+            Finish::FourKongAbort => "四開槓",
+            _ => "和了",
+        }
+    }
     pub fn has_bonus_hand(self) -> bool {
-        self.is_goulash_hand() || self.is_dealer_win()
+        match self {
+            Finish::ExaustiveDraw{tenpai, ..} => tenpai.contains(Wind::EAST),
+            _ => self.is_goulash_hand() || self.is_dealer_win()
+        }
     }
     pub fn payment(self, state: &mut State) {
-        let _ = state;
+        // a kan-dora still waiting for the discard counts for a rinshan or robbing-kong win
+        if let Finish::WinByDraw(..) | Finish::WinByDiscard(..) = self {
+            state.table.wall.flush_dora();
+        }
+        match self {
+            Finish::WinByDraw(winner, tile) => state.settle_win(winner, tile, None, true),
+            Finish::WinByDiscard(winners) => {
+                let discarded = *state.table.rivers.last().expect("Tiles not found on river");
+                let discarder = discarded.discarded_by();
+                for (i, winner) in winners.iter_from(discarder).enumerate() {
+                    state.settle_win(winner, discarded.tile(), Some(discarder), i == 0)
+                }
+            },
+            Finish::ExaustiveDraw{tenpai, nagashi} => state.settle_draw(tenpai, nagashi),
+            abort => state.broadcast(abort.show())
+        }
+        state.check_burst();
     }
 }
 
@@ -217,49 +289,173 @@ impl From<Finish> for Result<Step, failure::Error> {
         Ok(Step::Finish(finish))
     }
 }
+pub fn meld_options(land: &Tiles, wind: Wind, claim: Claim, figure: Figure, claimee: Wind) -> Vec<Set> {
+    let mut figures = land.clone().figures();
+    figures.add(figure);
+    match claim {
+        Claim::PUNG if figures.has_pung(figure) => vec![Set::from_shape_figure(Shape::PUNG, figure)],
+        Claim::KONG if figures.has_kong(figure) => vec![Set::from_shape_figure(Shape::KONG, figure)],
+        Claim::CHOW if figure.suit().is_numeric() && wind == claimee.rightside() => {
+            let r = figure.rank().id();
+            (r.saturating_sub(2)..=std::cmp::min(r, 6))
+                .map(|lo| Figure::from_suitrank(figure.suit(), Rank::from_id(lo)))
+                .filter(|&lo| figures.has_chow(lo))
+                .map(|lo| Set::from_shape_figure(Shape::CHOW, lo))
+                .collect()
+        },
+        _ => Vec::new()
+    }
+}
+
 pub struct Seat<'a> {
     pub wind: Wind,
     pub land: &'a mut Tiles,
     pub river: &'a mut Rivers,
     pub wall: &'a mut Wall,
     pub melds: &'a mut Melds,
-    pub player: &'a mut Player
+    pub norths: &'a mut [usize; Wind::N],
+    pub flowers: &'a Tiles,
+    pub player: &'a mut Player,
+    pub rules: &'a RuleSet
 }
 
 impl<'a> Seat<'a> {
     pub fn show_draw_phase(&mut self, drawn: Tile) {
         let mut tiles = self.land.clone();
+        let reds = self.rules.red_fives;
         let player = &mut self.player;
         writeln!(player, "{}家 ツモ番", self.wind.show());
+        self.show_indicators();
+        let player = &mut self.player;
         while let Some(tile) = tiles.next() {
-            write!(player, "{}", tile.figure().show());
+            write!(player, "{}", reds.show(tile));
         }
-        writeln!(player, " {}", drawn.figure().show());
+        writeln!(player, " {}", reds.show(drawn));
+        self.show_melds();
+        self.show_acceptance(Some(drawn));
     }
 
-    pub fn show_claim_phase(&mut self) {
+    pub fn show_discard_phase(&mut self) {
+        let mut tiles = self.land.clone();
+        let reds = self.rules.red_fives;
+        writeln!(self.player, "{}家 打牌", self.wind.show());
+        self.show_melds();
+        while let Some(tile) = tiles.next() {
+            write!(self.player, "{}", reds.show(tile));
+        }
+        writeln!(self.player, "");
+        self.show_acceptance(None);
+    }
+
+    pub fn show_melds(&mut self) {
+        let sets: Vec<Set> = self.melds.iter_wind(self.river, self.wind).map(|(_, m)| m.set()).collect();
+        if sets.is_empty() && self.norths[self.wind.id()] == 0 && self.flowers.is_empty() {
+            return
+        }
+        write!(self.player, "副露");
+        for set in sets {
+            write!(self.player, " ");
+            self.show_set(set);
+        }
+        if self.norths[self.wind.id()] > 0 {
+            write!(self.player, " 抜き北{}", self.norths[self.wind.id()]);
+        }
+        if !self.flowers.is_empty() {
+            let mut flowers = self.flowers.clone();
+            write!(self.player, " 花");
+            while let Some(tile) = flowers.next() {
+                write!(self.player, "{}", tile.show_flower());
+            }
+        }
+        writeln!(self.player, "");
+    }
+
+    pub fn show_acceptance(&mut self, drawn: Option<Tile>) {
+        let hand = self.hand();
+        let mut figures = hand.figures.clone();
+        let mut visible = self.visible_figures();
+        if let Some(drawn) = drawn {
+            figures.add(drawn.figure());
+            visible.add(drawn.figure());
+        }
+        let n = shanten::shanten(&figures, hand.melds.len());
+        if n < 0 {
+            return
+        }
+        writeln!(self.player, "{}向聴", n);
+        let mut acceptance = shanten::acceptance(&figures, hand.melds.len(), &visible, &self.wall.figures());
+        acceptance.retain(|a| a.shanten == n);
+        acceptance.sort_by_key(|a| std::cmp::Reverse(a.count()));
+        for a in acceptance {
+            write!(self.player, "打{} ", a.discard.show());
+            for (f, _) in a.tiles.iter() {
+                write!(self.player, "{}", f.show());
+            }
+            writeln!(self.player, " {}枚", a.count());
+        }
+    }
+
+    pub fn show_indicators(&mut self) {
+        let indicators = self.wall.dora_indicators();
+        if indicators.is_empty() {
+            return
+        }
+        write!(self.player, "ドラ表示牌");
+        for t in indicators {
+            write!(self.player, "{}", t.figure().show());
+        }
+        writeln!(self.player, "");
+    }
+
+    pub fn visible_figures(&self) -> Figures {
+        let mut figures = self.land.clone().figures();
+        for t in self.wall.dora_indicators() {
+            figures.add(t.figure());
+        }
+        for d in self.river.iter().filter(|d| !d.is_robbed()) {
+            figures.add(d.tile().figure());
+        }
+        for m in self.melds.iter() {
+            figures.add_set(m.set());
+        }
+        for _ in 0..self.norths.iter().sum() {
+            figures.add(Wind::NORTH.figure());
+        }
+        figures
+    }
+
+    pub fn show_claim_phase(&mut self, furiten: bool) {
         let mut s = String::new();
         let player = &mut self.player;
         let s = self.wind;
-        let river = &mut self.river;
+        let reds = self.rules.red_fives;
         writeln!(player, "{}家 鳴き番", s.show());
-        for o in s.others() {
+        self.show_indicators();
+        let player = &mut self.player;
+        let river = &mut self.river;
+        for o in s.others_of(self.rules.seats).chain(Some(s)) {
+            let sideways = river.sideways(o);
             write!(player, "{}家河", o.show());
-            river.iter().filter(|d| d.discarded_by() == o).for_each(|d| {
-                write!(player, "{}", d.tile().figure().show());
+            river.iter().enumerate().filter(|(_, d)| d.discarded_by() == o && !d.is_robbed()).for_each(|(i, d)| {
+                write!(player, "{}{}", if sideways == Some(i) { "横" } else { "" }, reds.show(d.tile()));
             });
             writeln!(player, "");
         }
-        write!(player, "{}家河", s.show());
-        river.iter().filter(|d| d.discarded_by() == s).for_each(|d| {
-            write!(player, "{}", d.tile().figure().show());
-        });
-        writeln!(player, "");
         let mut tiles = self.land.clone();
         while let Some(tile) = tiles.next() {
-            write!(player, "{}", tile.figure().show());
+            write!(player, "{}", reds.show(tile));
         }
         writeln!(player, "");
+        self.show_melds();
+        let hand = self.hand();
+        let waits = shanten::waits(&hand.figures, hand.melds.len());
+        if !waits.is_empty() {
+            write!(self.player, "待ち");
+            for f in waits {
+                write!(self.player, "{}", f.show());
+            }
+            writeln!(self.player, "{}", if furiten { " 振聴" } else { "" });
+        }
     }
 
     pub fn take_tile_into_hand(&mut self, tile: Tile) {
@@ -268,6 +464,14 @@ impl<'a> Seat<'a> {
     pub fn remove_tile_from_hand(&mut self, tile: Tile) {
         self.land.del(tile)
     }
+    pub fn hand(&self) -> Hand {
+        Hand::new(self.land, self.melds, self.river, self.wind)
+    }
+    pub fn is_mahjong(&self, tile: Tile) -> bool {
+        let hand = self.hand();
+        hand.is_mahjong(tile.figure())
+            || (self.rules.style == Style::Mcr && !hand.decompose_mcr(tile.figure()).is_empty())
+    }
     pub fn has_meld(&mut self) {
 
     }
@@ -283,6 +487,30 @@ impl<'a> Seat<'a> {
             .and_then(|line| Claim::parse(&line))
             .unwrap_or_default()
     }
+    pub fn get_legal_claim(&mut self, legal: &[Claim]) -> Claim {
+        for claim in legal.iter().chain(Some(&Claim::THROUGH)) {
+            write!(self.player, "{} ", claim.show());
+        }
+        writeln!(self.player, "");
+        loop {
+            let line = match self.player.get_line() {
+                Ok(line) => line,
+                Err(_) => return Claim::THROUGH
+            };
+            if line.trim().is_empty() {
+                return Claim::THROUGH
+            }
+            match Claim::parse(&line) {
+                Ok(claim) if claim == Claim::THROUGH || legal.contains(&claim) => return claim,
+                Ok(claim) => {
+                    writeln!(self.player, "Can not claim {}", claim.show());
+                },
+                Err(e) => {
+                    writeln!(self.player, "{}", e);
+                }
+            }
+        }
+    }
     pub fn get_choice(&mut self) -> Choice {
         self.player.get_line()
             .and_then(|line| Choice::parse(&line))
@@ -316,8 +544,11 @@ impl<'a> Seat<'a> {
             ref river,
             wind,
             ref mut land,
+            norths: _,
+            flowers: _,
             wall: _,
-            player: _
+            player: _,
+            rules: _
         } = *self;
         melds.iter_wind(river, wind).find_map(|(i, m)|
             if m.set() == Set::from_shape_figure(Shape::PUNG, figure) {
@@ -327,54 +558,183 @@ impl<'a> Seat<'a> {
             }
         ).and_then(move |i| land.extract(figure).map(|t| (t, i)))
     }
-    pub fn do_choice(&mut self, choice: Choice, tile: Tile) -> Result<Step, failure::Error> {
+    pub fn meld_options(&self, claim: Claim, figure: Figure, claimee: Wind) -> Vec<Set> {
+        meld_options(self.land, self.wind, claim, figure, claimee)
+    }
+    pub fn show_set(&mut self, set: Set) {
+        let suit = set.figure().suit();
+        let mut ranks = set.ranks();
+        while let Some(r) = ranks.next() {
+            write!(self.player, "{}", Figure::from_suitrank(suit, r).show());
+        }
+    }
+    pub fn choose_set(&mut self, sets: &[Set]) -> Set {
+        if sets.len() == 1 {
+            return sets[0]
+        }
+        for &set in sets {
+            self.show_set(set);
+            write!(self.player, " ");
+        }
+        writeln!(self.player, "");
+        loop {
+            let line = match self.player.get_line() {
+                Ok(line) => line,
+                Err(_) => return sets[0]
+            };
+            let chosen = line.split_whitespace().last()
+                .and_then(Figure::parse)
+                .and_then(|f| sets.iter().find(|s| s.figure() == f));
+            match chosen {
+                Some(&set) => return set,
+                None => {
+                    writeln!(self.player, "Choose the lowest tile of a set");
+                }
+            }
+        }
+    }
+    pub fn make_meld(&mut self, set: Set, claimed: Figure) -> Result<Step, failure::Error> {
+        let reds = self.rules.red_fives;
+        let suit = claimed.suit();
+        let mut ranks = set.ranks();
+        ranks.del(claimed.rank());
+        let mut taken = Vec::new();
+        while let Some(r) = ranks.next() {
+            let figure = Figure::from_suitrank(suit, r);
+            let tile = self.land.extract_with(figure, reds, false)
+                .ok_or(failure::err_msg(format!("Can not meld {}", figure.show())))?;
+            taken.push(tile);
+        }
+        let (tile, index) = self.river.rob(self.wind).expect("Tiles not found on river");
+        taken.push(tile);
+        let mut meld = Meld::from_set_robinfo(set, index);
+        meld.set_reds(taken.iter().filter(|&&t| reds.is_red(t)).count());
+        if set.shape() == Shape::CHOW {
+            meld.set_claimed(claimed.rank().id() - set.figure().rank().id());
+        }
+        self.melds.add(meld);
+        if set.shape() == Shape::KONG {
+            Phase::Replace{wind: self.wind, expose: true}.into()
+        } else {
+            Phase::Discard{wind: self.wind}.into()
+        }
+    }
+    pub fn check_choice(&self, choice: Choice, legal: &[Choice]) -> Result<(), failure::Error> {
+        if legal.contains(&choice) {
+            Ok(())
+        } else {
+            Err(failure::err_msg(format!("Can not {}", choice.show())))
+        }
+    }
+    pub fn show_choices(&mut self, legal: &[Choice]) {
+        let mut options = legal.iter()
+            .filter(|c| !matches!(c, Choice::Discard{riichi: false, ..} | Choice::DrawAndDiscard{riichi: false}))
+            .peekable();
+        if options.peek().is_none() {
+            return
+        }
+        for choice in options {
+            write!(self.player, "{} ", choice.show());
+        }
+        writeln!(self.player, "");
+    }
+    pub fn do_discard(&mut self, choice: Choice, legal: &[Choice]) -> Result<Step, failure::Error> {
+        if let Choice::DrawAndDiscard{..} = choice {
+            return Err(failure::err_msg("No drawn tile to discard"))
+        }
+        self.check_choice(choice, legal)?;
+        let discard = match choice {
+            Choice::Discard{figure, red, ..} => self.land.extract_with(figure, self.rules.red_fives, red)
+                .ok_or(failure::err_msg(format!("Can not discard {}", figure.show())))?,
+            _ => return Err(failure::err_msg("Discard a tile"))
+        };
+        self.throw_tile_into_river(discard);
+        Phase::Ask.into()
+    }
+    pub fn do_choice(&mut self, choice: Choice, tile: Tile, legal: &[Choice]) -> Result<Step, failure::Error> {
+        self.check_choice(choice, legal)?;
         match choice {
-            Choice::Discard{figure, riichi} => {
-                let discard = self.extract_tile_from_hand(figure)
-                    .ok_or(failure::err_msg(format!("Can not discard {}", figure.show())))?;
+            Choice::Discard{figure, red, riichi} => {
                 self.take_tile_into_hand(tile);
+                let discard = match self.land.extract_with(figure, self.rules.red_fives, red) {
+                    Some(discard) => discard,
+                    None => {
+                        self.remove_tile_from_hand(tile);
+                        return Err(failure::err_msg(format!("Can not discard {}", figure.show())))
+                    }
+                };
                 self.throw_tile_into_river(discard);
+                if riichi {
+                    self.river.declare_riichi();
+                }
                 Phase::Ask.into()
             },
             Choice::DrawAndDiscard{riichi} => {
                 self.throw_tile_into_river(tile);
+                if riichi {
+                    self.river.declare_riichi();
+                }
                 Phase::Ask.into()
             },
             Choice::Kong{figure} => {
-                self.land.add(tile);
+                let reds = self.rules.red_fives;
+                self.take_tile_into_hand(tile);
                 if let Some(ts) = self.kong_extract(figure) {
                     self.river.add(self.wind, ts[0]);
-                    let (t, i) = self.river.rob(self.wind).expect("hoge");
-                    let meld = Meld::from_set_robinfo(Set::from_shape_figure(Shape::KONG, t.figure()), i);
+                    let (_, i) = self.river.rob(self.wind).expect("Tiles not found on river");
+                    let mut meld = Meld::from_set_robinfo(Set::from_shape_figure(Shape::KONG, figure), i);
+                    meld.set_reds(ts.iter().filter(|&&t| reds.is_red(t)).count());
                     self.melds.add(meld);
-                    return Phase::Replace{wind: self.wind, expose: false}.into();
+                    if self.rules.kokushi_robs_concealed_kong {
+                        return Phase::AskRobbingKong{index: self.melds.slice().len() - 1}.into()
+                    }
+                    return Phase::Replace{wind: self.wind, expose: false}.into()
                 }
                 if let Some((t, i)) = self.adding_kong_extract(figure) {
                     self.river.add(self.wind, t);
-                    return Phase::Ask.into()
+                    let meld = &mut self.melds.slice_mut()[i];
+                    let n = meld.reds() + reds.is_red(t) as usize;
+                    meld.set_reds(n);
+                    return Phase::AskRobbingKong{index: i}.into()
                 }
-                if let Some(ts) = self.land.extract_set(Set::from_shape_figure(Shape::KONG, figure)) {
-
-                }
-                if let Some(t) = self.land.extract(figure) {
-                    for m in self.melds.iter_mut() {
-                        if m.set() == Set::from_shape_figure(Shape::KONG, figure) && m.wind(self.river).unwrap() == self.wind {
-                        }
-                    }
-                }
-                self.land.del(tile);
-                return Err(failure::err_msg("can not make kong"))
+                self.remove_tile_from_hand(tile);
+                Err(failure::err_msg(format!("Can not make kong {}", figure.show())))
             },
             Choice::Mahjong => {
-                unimplemented!()
+                if self.is_mahjong(tile) {
+                    Finish::WinByDraw(self.wind, tile).into()
+                } else {
+                    Err(failure::err_msg(format!("Can not win with {}", tile.figure().show())))
+                }
             },
-            _ => unimplemented!()
+            Choice::North => {
+                self.take_tile_into_hand(tile);
+                let _ = self.extract_tile_from_hand(Wind::NORTH.figure())
+                    .ok_or(failure::err_msg("No north tile in hand"))?;
+                self.norths[self.wind.id()] += 1;
+                Phase::North{wind: self.wind}.into()
+            },
+            Choice::NineTerminals => Finish::NineTerminalAbort.into()
         }
     }
 
 }
 
 impl<'a> State<'a> {
+    pub fn new(rules: &'a RuleSet, sticks: &'a mut Sticks, round: Wind, dealer: usize, table: &'a mut Table, players: &'a mut [Player], dice: Dice) -> Self {
+        State {
+            status: Default::default(),
+            rinshan: false,
+            chankan: false,
+            table,
+            sticks,
+            round,
+            dice,
+            dealer,
+            players,
+            rules,
+        }
+    }
     pub fn seat(&mut self, wind: Wind) -> Seat {
         let pid = self.pid(wind);
         Seat {
@@ -383,80 +743,605 @@ impl<'a> State<'a> {
             river: &mut self.table.rivers,
             wall: &mut self.table.wall,
             melds: &mut self.table.lands.melds,
+            norths: &mut self.table.lands.norths,
+            flowers: &self.table.lands.flowers[wind.id()],
             player: &mut self.players[pid],
+            rules: self.rules,
         }
     }
     pub fn pid(&self, wind: Wind) -> usize {
-        (self.dealer + wind.id()) % 4
+        (self.dealer + wind.id()) % self.rules.seats
+    }
+    pub fn hand(&self, wind: Wind) -> Hand {
+        let lands = &self.table.lands;
+        Hand::new(&lands.tiles[wind.id()], &lands.melds, &self.table.rivers, wind)
+    }
+    pub fn context(&self, wind: Wind, winning: Tile, tsumo: bool) -> Context {
+        let wall = &self.table.wall;
+        let mut ctx = Context {
+            tsumo,
+            last_tile: wall.live() == 0,
+            first_turn: self.is_first_turn(wind),
+            riichi: self.status[wind.id()].riichi,
+            double_riichi: self.status[wind.id()].double_riichi,
+            ippatsu: self.status[wind.id()].ippatsu,
+            open_tanyao: self.rules.open_tanyao,
+            double_yakuman: self.rules.double_yakuman,
+            rinshan: tsumo && self.rinshan,
+            chankan: !tsumo && self.chankan,
+            dora: self.count_dora(wind, winning, false),
+            aka_dora: self.count_reds(wind, winning),
+            flowers: self.table.lands.flowers[wind.id()].count(),
+            last_copy: self.is_last_copy(winning),
+            ..Context::new(wind, self.round)
+        };
+        if ctx.riichi && self.rules.ura_dora {
+            ctx.ura_dora = self.count_dora(wind, winning, true);
+        }
+        ctx
+    }
+    // each north set aside counts as a dora and again for every indicator pointing to north
+    pub fn count_dora(&self, wind: Wind, winning: Tile, ura: bool) -> usize {
+        let doras = self.table.wall.doras(ura);
+        let norths = self.table.lands.norths[wind.id()];
+        let hits = doras.iter().filter(|&&f| f == Wind::NORTH.figure()).count();
+        self.hand(wind).count_dora(winning.figure(), &doras) + norths * (hits + !ura as usize)
+    }
+    // the other three copies of the winning figure are already in the rivers or exposed melds
+    pub fn is_last_copy(&self, winning: Tile) -> bool {
+        let figure = winning.figure();
+        let rivers = &self.table.rivers;
+        let discarded = rivers.iter()
+            .filter(|d| !d.is_robbed() && d.tile() != winning && d.tile().figure() == figure)
+            .count();
+        let exposed: usize = self.table.lands.melds.iter()
+            .filter(|m| !m.is_concealed(rivers))
+            .map(|m| {
+                let mut figures = Figures::new();
+                figures.add_set(m.set());
+                figures.get(figure)
+            })
+            .sum();
+        discarded + exposed == 3
+    }
+    pub fn is_first_turn(&self, wind: Wind) -> bool {
+        self.table.lands.melds.slice().is_empty()
+            && self.table.rivers.iter().all(|d| d.discarded_by() != wind)
+    }
+    pub fn waits(&self, wind: Wind) -> Vec<Figure> {
+        let hand = self.hand(wind);
+        shanten::waits(&hand.figures, hand.melds.len())
+    }
+    pub fn is_furiten(&self, wind: Wind) -> bool {
+        if self.rules.style == Style::Mcr {
+            return false
+        }
+        let status = self.status[wind.id()];
+        let waits = self.waits(wind);
+        status.temporary_furiten || status.riichi_furiten
+            || self.table.rivers.iter()
+                .filter(|d| d.discarded_by() == wind && d.robbed_by() != Some(wind))
+                .any(|d| waits.contains(&d.tile().figure()))
+    }
+    pub fn can_win(&self, wind: Wind, winning: Tile, tsumo: bool) -> bool {
+        let hand = self.hand(wind);
+        if self.rules.style == Style::Mcr {
+            return fan::best(&hand, winning.figure(), &self.context(wind, winning, tsumo))
+                .is_some_and(|fans| fan::is_enough(&fans))
+        }
+        hand.is_mahjong(winning.figure())
+            && points::best(&hand, winning.figure(), &self.context(wind, winning, tsumo), self.rules).is_some()
+    }
+    pub fn legal_choices(&self, wind: Wind, drawn: Option<Tile>) -> Vec<Choice> {
+        let reds = self.rules.red_fives;
+        let wall = &self.table.wall;
+        let hand = self.hand(wind);
+        let mut tiles = self.table.lands.tiles[wind.id()].clone();
+        if let Some(tile) = drawn {
+            tiles.add(tile);
+        }
+        let figures = tiles.clone().figures();
+        let n_melds = hand.melds.len();
+        let status = self.status[wind.id()];
+        let can_riichi = drawn.is_some() && self.rules.style == Style::Riichi && hand.is_closed() && !status.riichi
+            && wall.live() >= 4 && self.sticks.score[self.pid(wind)] >= 1000;
+        let is_tenpai_without = |figure: Figure| {
+            let mut rest = figures.clone();
+            rest.del(figure);
+            shanten::shanten(&rest, n_melds) == 0
+        };
+        let mut legal = Vec::new();
+        if let Some(tile) = drawn {
+            legal.push(Choice::DrawAndDiscard{riichi: false});
+            if can_riichi && is_tenpai_without(tile.figure()) {
+                legal.push(Choice::DrawAndDiscard{riichi: true});
+            }
+        }
+        for figure in Figure::mk_iter().filter(|&f| figures.get(f) > 0 && !status.riichi) {
+            let riichi = can_riichi && is_tenpai_without(figure);
+            let has_red = (0..4).any(|spec| {
+                let t = Tile::from_id(4 * figure.id() + spec);
+                tiles.has(t) && reds.is_red(t)
+            });
+            for &red in [false, true].iter().filter(|&&red| !red || has_red) {
+                legal.push(Choice::Discard{figure, red, riichi: false});
+                if riichi {
+                    legal.push(Choice::Discard{figure, red, riichi: true});
+                }
+            }
+        }
+        let tile = match drawn {
+            Some(tile) => tile,
+            None => {
+                let forbidden = self.kuikae(wind);
+                let allowed: Vec<Choice> = legal.iter().cloned().filter(|&choice| match choice {
+                    Choice::Discard{figure, ..} => !forbidden.contains(&figure),
+                    _ => true
+                }).collect();
+                return if allowed.is_empty() { legal } else { allowed }
+            }
+        };
+        if wall.can_replace() {
+            for figure in Figure::mk_iter() {
+                let added = hand.melds.iter().any(|g| !g.concealed && g.set == Set::from_shape_figure(Shape::PUNG, figure));
+                let kong = if status.riichi {
+                    figure == tile.figure() && figures.get(figure) == 4 && {
+                        let mut after = hand.figures.clone();
+                        for _ in 0..3 {
+                            after.del(figure);
+                        }
+                        shanten::waits(&after, n_melds + 1) == shanten::waits(&hand.figures, n_melds)
+                    }
+                } else {
+                    figures.get(figure) == 4 || (added && figures.get(figure) > 0)
+                };
+                if kong {
+                    legal.push(Choice::Kong{figure});
+                }
+            }
+        }
+        let north = Wind::NORTH.figure();
+        if self.rules.seats == 3 && wall.can_replace() && figures.get(north) > 0 && (!status.riichi || tile.figure() == north) {
+            legal.push(Choice::North);
+        }
+        if self.rules.abortive_draws.nine_terminals && self.is_first_turn(wind) && Figure::mk_iter().filter(|&f| f.is_orphan() && figures.get(f) > 0).count() >= 9 {
+            legal.push(Choice::NineTerminals);
+        }
+        if self.can_win(wind, tile, true) {
+            legal.push(Choice::Mahjong);
+        }
+        legal
+    }
+    pub fn kuikae(&self, wind: Wind) -> Vec<Figure> {
+        let rivers = &self.table.rivers;
+        let meld = match self.table.lands.melds.last() {
+            Some(&meld) if meld.wind(rivers) == Some(wind) => meld,
+            _ => return Vec::new()
+        };
+        let set = meld.set();
+        let (suit, rank) = (set.figure().suit(), set.figure().rank().id());
+        let mut forbidden = Vec::new();
+        match (set.shape(), self.rules.kuikae) {
+            (_, Kuikae::None) => {},
+            (Shape::PUNG, _) => forbidden.push(set.figure()),
+            (Shape::CHOW, kuikae) => {
+                forbidden.push(Figure::from_suitrank(suit, Rank::from_id(rank + meld.claimed())));
+                if kuikae == Kuikae::Suji {
+                    match meld.claimed() {
+                        0 if rank + 3 < 9 => forbidden.push(Figure::from_suitrank(suit, Rank::from_id(rank + 3))),
+                        2 if rank > 0 => forbidden.push(Figure::from_suitrank(suit, Rank::from_id(rank - 1))),
+                        _ => {}
+                    }
+                }
+            },
+            _ => {}
+        }
+        forbidden
+    }
+    pub fn legal_claims(&self, wind: Wind, robbing: bool) -> Vec<Claim> {
+        let discarded = *self.table.rivers.last().expect("Tiles not found on river");
+        let (claimee, tile) = (discarded.discarded_by(), discarded.tile());
+        let wall = &self.table.wall;
+        let mut legal = Vec::new();
+        if wind == claimee {
+            return legal
+        }
+        let kokushi_only = robbing && discarded.is_robbed();
+        if self.can_win(wind, tile, false) && !self.is_furiten(wind)
+            && (!kokushi_only || is_thirteen_orphans(&self.hand(wind).all_figures(tile.figure()))) {
+            legal.push(Claim::MAHJONG);
+        }
+        if robbing || wall.live() == 0 || self.status[wind.id()].riichi {
+            return legal
+        }
+        let land = &self.table.lands.tiles[wind.id()];
+        for &claim in [Claim::KONG, Claim::PUNG, Claim::CHOW].iter() {
+            if (claim == Claim::KONG && !wall.can_replace()) || (claim == Claim::CHOW && self.rules.seats == 3) {
+                continue
+            }
+            if !meld_options(land, wind, claim, tile.figure(), claimee).is_empty() {
+                legal.push(claim);
+            }
+        }
+        legal
+    }
+    pub fn count_reds(&self, wind: Wind, winning: Tile) -> usize {
+        let reds = self.rules.red_fives;
+        let lands = &self.table.lands;
+        let melds: usize = lands.melds.iter_wind(&self.table.rivers, wind).map(|(_, m)| m.reds()).sum();
+        reds.count(&lands.tiles[wind.id()]) + reds.is_red(winning) as usize + melds
+    }
+    pub fn broadcast(&mut self, message: &str) {
+        for player in self.players.iter_mut() {
+            writeln!(player, "{}", message);
+        }
+    }
+    pub fn settle_win(&mut self, winner: Wind, tile: Tile, discarder: Option<Wind>, nearest: bool) {
+        if self.rules.style == Style::Mcr {
+            return self.settle_fan(winner, tile, discarder)
+        }
+        let ctx = self.context(winner, tile, discarder.is_none());
+        let (score, yakus) = points::best(&self.hand(winner), tile.figure(), &ctx, self.rules)
+            .expect("can_win guarantees a yaku");
+        let stack = if nearest { self.sticks.stack } else { 0 };
+        let pao = self.status[winner.id()].pao
+            .and_then(|(liable, pao)| yakus.iter().find(|&&(y, _)| y == pao).map(|&(_, n)| (liable, n)))
+            .filter(|&(liable, _)| Some(liable) != discarder);
+        let mut bills = Vec::new();
+        if let Some((liable, n)) = pao {
+            let n = if self.rules.multiple_yakuman { n } else { score.yakuman };
+            let rest = Score { yakuman: score.yakuman - n, ..score };
+            let points: isize = Score { yakuman: n, ..score }.payments(self.rules, winner, discarder)
+                .iter().map(|&(_, points)| points).sum();
+            match discarder {
+                Some(discarder) => {
+                    bills.push((liable, points / 2));
+                    bills.push((discarder, points / 2 + 300 * stack));
+                },
+                None => bills.push((liable, points + 300 * stack)),
+            }
+            if rest.yakuman > 0 {
+                bills.extend(rest.payments(self.rules, winner, discarder));
+            }
+        } else {
+            let honba = stack * if discarder.is_some() { 300 } else { 100 };
+            bills.extend(score.payments(self.rules, winner, discarder).into_iter().map(|(payer, points)| (payer, points + honba)));
+        }
+        let mut total = 0;
+        for (payer, points) in bills {
+            let (payer, receiver) = (self.pid(payer), self.pid(winner));
+            self.sticks.payment(payer, receiver, points);
+            total += points;
+        }
+        let deposit = if nearest { self.sticks.deposit } else { 0 };
+        let pid = self.pid(winner);
+        self.sticks.score[pid] += deposit;
+        self.sticks.deposit -= deposit;
+        let mut message = format!("{}家 和了 {}", winner.show(), self.rules.red_fives.show(tile));
+        for (yaku, n) in yakus {
+            message += &format!(" {}{}", yaku.show(), if yaku.is_yakuman() { String::new() } else { format!("{}飜", n) });
+        }
+        message += &format!(" {}符{}飜 {} {}点", score.fu, score.han, score.limit(self.rules).show(), total + deposit);
+        if let Some((liable, _)) = pao {
+            message += &format!(" 包{}家", liable.show());
+        }
+        self.broadcast(&message);
+    }
+    pub fn settle_fan(&mut self, winner: Wind, tile: Tile, discarder: Option<Wind>) {
+        let ctx = self.context(winner, tile, discarder.is_none());
+        let fans = fan::best(&self.hand(winner), tile.figure(), &ctx).unwrap_or_default();
+        let sum = fan::points(&fans);
+        let mut total = 0;
+        for (payer, points) in points::fan_payments(sum, winner, discarder, self.rules.seats) {
+            let (payer, receiver) = (self.pid(payer), self.pid(winner));
+            self.sticks.payment(payer, receiver, points);
+            total += points;
+        }
+        let mut message = format!("{}家 和了 {}", winner.show(), tile.figure().show());
+        for (fan, n) in fans {
+            message += &format!(" {}{}番", fan.show(), fan.points() * n);
+        }
+        message += &format!(" {}番 {}点", sum, total);
+        self.broadcast(&message);
+    }
+    pub fn exhaust(&mut self) -> Finish {
+        let mut tenpai = Winds::new();
+        if self.rules.style == Style::Mcr {
+            return Finish::ExaustiveDraw{tenpai, nagashi: Winds::new()}
+        }
+        for wind in Wind::make_seats(self.rules.seats) {
+            if self.waits(wind).is_empty() {
+                continue
+            }
+            let declared = !self.rules.declare_tenpai || self.status[wind.id()].riichi || {
+                let seat = &mut self.seat(wind);
+                writeln!(seat.player, "{}家 Tenpai Noten", wind.show());
+                seat.player.get_line().is_ok_and(|line| line.trim() == "Tenpai")
+            };
+            if declared {
+                tenpai.add(wind);
+            }
+        }
+        let mut nagashi = Winds::new();
+        if self.rules.nagashi_mangan {
+            for wind in Wind::make_seats(self.rules.seats) {
+                let mut discards = self.table.rivers.iter()
+                    .filter(|d| d.discarded_by() == wind && d.robbed_by() != Some(wind))
+                    .peekable();
+                if discards.peek().is_some() && discards.all(|d| !d.is_robbed() && d.tile().figure().is_orphan()) {
+                    nagashi.add(wind);
+                }
+            }
+        }
+        Finish::ExaustiveDraw{tenpai, nagashi}
+    }
+    pub fn settle_draw(&mut self, tenpai: Winds, nagashi: Winds) {
+        let mut message = "流局".to_string();
+        if !nagashi.is_empty() {
+            let mangan = Score { han: 5, fu: 30, yakuman: 0 };
+            for winner in nagashi.iter() {
+                let mut total = 0;
+                for (payer, points) in mangan.payments(self.rules, winner, None) {
+                    let (payer, receiver) = (self.pid(payer), self.pid(winner));
+                    self.sticks.payment(payer, receiver, points);
+                    total += points;
+                }
+                message += &format!(" {}家流し満貫 {}点", winner.show(), total);
+            }
+        } else {
+            let (n, seats) = (tenpai.count() as isize, self.rules.seats as isize);
+            if n > 0 && n < seats {
+                for noten in Wind::make_seats(self.rules.seats).filter(|&w| !tenpai.contains(w)) {
+                    for receiver in tenpai.iter() {
+                        let (payer, receiver) = (self.pid(noten), self.pid(receiver));
+                        self.sticks.payment(payer, receiver, 1000 * (seats - 1) / (n * (seats - n)));
+                    }
+                }
+            }
+        }
+        for w in tenpai.iter() {
+            message += &format!(" {}家聴牌", w.show());
+        }
+        self.broadcast(&message);
+    }
+    pub fn check_burst(&mut self) {
+        for (i, &score) in self.sticks.score.iter().enumerate() {
+            if score < 0 {
+                self.sticks.burst_flags |= 1 << i;
+            }
+        }
     }
     pub fn draw(&mut self, seat: Wind) -> Result<Step, failure::Error> {
+        self.rinshan = false;
         if let Some(tile) = self.table.draw_tile() {
-            self.choose(seat, tile).into()
+            self.choose(seat, tile)
         } else {
-            Finish::ExaustiveDraw.into()
+            self.exhaust().into()
         }
     }
     pub fn replace(&mut self, seat: Wind, expose: bool) -> Result<Step, failure::Error> {
+        self.break_ippatsu();
+        if expose && self.rules.kan_dora_after_discard {
+            self.table.wall.defer_dora();
+        } else {
+            self.table.wall.reveal_dora();
+        }
+        self.rinshan = true;
         if let Some(tile) = self.table.draw_replacement() {
-            self.choose(seat, tile).into()
+            self.choose(seat, tile)
         } else {
-            Finish::ExaustiveDraw.into()
+            self.exhaust().into()
         }
     }
-    pub fn meld(&mut self, seat: Wind, claim: Claim) -> Result<Step, failure::Error> {
-        let _ = (seat, claim);
-        unimplemented!()
+    pub fn north(&mut self, seat: Wind) -> Result<Step, failure::Error> {
+        self.broadcast(&format!("{}家 抜き北", seat.show()));
+        self.rinshan = true;
+        if let Some(tile) = self.table.draw_replacement() {
+            self.choose(seat, tile)
+        } else {
+            self.exhaust().into()
+        }
+    }
+    pub fn meld(&mut self, wind: Wind, claim: Claim) -> Result<Step, failure::Error> {
+        let discarded = *self.table.rivers.last().expect("Tiles not found on river");
+        let claimee = discarded.discarded_by();
+        let figure = discarded.tile().figure();
+        self.break_ippatsu();
+        let seat = &mut self.seat(wind);
+        let sets = seat.meld_options(claim, figure, claimee);
+        if sets.is_empty() {
+            writeln!(seat.player, "鳴けません");
+            return Phase::Draw{wind: claimee.next_of(self.rules.seats)}.into()
+        }
+        let set = seat.choose_set(&sets);
+        let step = seat.make_meld(set, figure)?;
+        self.check_pao(wind, claimee, set);
+        Ok(step)
+    }
+    pub fn check_pao(&mut self, wind: Wind, feeder: Wind, set: Set) {
+        let hand = self.hand(wind);
+        let count = |kind: fn(&Figure) -> bool| hand.melds.iter()
+            .filter(|g| g.set.shape() != Shape::CHOW && kind(&g.set.figure()))
+            .count();
+        let figure = set.figure();
+        let yaku = match set.shape() {
+            Shape::CHOW => None,
+            _ if figure.is_dragon() && count(Figure::is_dragon) == 3 => Some(Yaku::Daisangen),
+            _ if figure.is_wind() && count(Figure::is_wind) == 4 => Some(Yaku::Daisuushii),
+            Shape::KONG if self.rules.pao_four_kongs && hand.melds.iter().filter(|g| g.set.shape() >= Shape::KONG).count() == 4 =>
+                Some(Yaku::Suukantsu),
+            _ => None
+        };
+        if yaku.is_some() {
+            self.status[wind.id()].pao = yaku.map(|yaku| (feeder, yaku));
+        }
     }
     pub fn choose(&mut self, turn: Wind, tile: Tile) -> Result<Step, failure::Error> {
+        if tile.is_flower() {
+            self.table.lands.flowers[turn.id()].add(tile);
+            self.broadcast(&format!("{}家 補花 {}", turn.show(), tile.show_flower()));
+            return match self.table.draw_replacement() {
+                Some(tile) => self.choose(turn, tile),
+                None => self.exhaust().into()
+            }
+        }
+        let legal = self.legal_choices(turn, Some(tile));
+        let auto = self.status[turn.id()].riichi && legal == [Choice::default()];
         let seat = &mut self.seat(turn);
         seat.show_draw_phase(tile);
-        let choice = seat.get_choice();
-        seat.do_choice(choice, tile)
+        if auto {
+            writeln!(seat.player, "ツモ切り");
+            return seat.do_choice(Choice::default(), tile, &legal)
+        }
+        seat.show_choices(&legal);
+        loop {
+            let choice = seat.get_choice();
+            match seat.do_choice(choice, tile, &legal) {
+                Ok(step) => return Ok(step),
+                Err(e) => {
+                    writeln!(seat.player, "{}", e);
+                }
+            }
+        }
     }
-    pub fn ask_robbing_kong(&mut self, index: usize) -> Result<Step, failure::Error> {
-        let claimee = self.table.rivers.last().expect("Tiles not found on river").discarded_by();
-        let mut claims = Claims::new(claimee);
-        for claimer in claimee.others() {
-            let seat = &mut self.seat(claimer);
-            seat.show_claim_phase();
-            let claim = seat.get_claim();
+    pub fn collect_claims(&mut self, claimee: Wind, robbing: bool) -> Claims {
+        let mut claims = Claims::new(claimee, self.rules.seats);
+        let discarded = *self.table.rivers.last().expect("Tiles not found on river");
+        let figure = discarded.tile().figure();
+        // only thirteen orphans may rob a concealed kong, so other waits do not pass it up
+        let kokushi_only = robbing && discarded.is_robbed();
+        for claimer in claimee.others_of(self.rules.seats) {
+            let legal = self.legal_claims(claimer, robbing);
+            let furiten = self.is_furiten(claimer);
+            let claim = if legal.is_empty() {
+                Claim::THROUGH
+            } else {
+                let seat = &mut self.seat(claimer);
+                seat.show_claim_phase(furiten);
+                seat.get_legal_claim(&legal)
+            };
             claims.add(claim, claimer);
+            let could_win = !kokushi_only || is_thirteen_orphans(&self.hand(claimer).all_figures(figure));
+            if claim != Claim::MAHJONG && could_win && self.waits(claimer).contains(&figure) {
+                let status = &mut self.status[claimer.id()];
+                if status.riichi {
+                    status.riichi_furiten = true;
+                } else {
+                    status.temporary_furiten = true;
+                }
+            }
         }
-        if let Some((claim, claimer)) = claims.next() {
-            match claim {
-                Claim::MAHJONG => Finish::WinByDiscard(claimer).into(),
-                _ => Phase::Draw{wind: claimee.rightside()}.into()
+        claims
+    }
+    pub fn take_winners(&self, claims: &mut Claims) -> (Winds, Option<(Claim, Wind)>) {
+        let mut winners = Winds::new();
+        let mut next = claims.next();
+        while let Some((Claim::MAHJONG, claimer)) = next {
+            if winners.count() < self.rules.multi_ron.max_winners() {
+                winners.add(claimer);
             }
-        } else {
-            let seat = &mut self.seat(claimee);
-            let meld = seat.melds.slice_mut().get_mut(index).unwrap();
-            meld.set_addinfo(index);
-            Phase::Replace{wind: claimee, expose: true}.into()
+            next = claims.next();
+        }
+        (winners, next)
+    }
+    pub fn ask_robbing_kong(&mut self, index: usize) -> Result<Step, failure::Error> {
+        let discarded = *self.table.rivers.last().expect("Tiles not found on river");
+        let claimee = discarded.discarded_by();
+        let concealed = discarded.is_robbed();
+        self.chankan = true;
+        let mut claims = self.collect_claims(claimee, true);
+        let (winners, _) = self.take_winners(&mut claims);
+        if !winners.is_empty() {
+            return Finish::WinByDiscard(winners).into()
+        }
+        self.chankan = false;
+        if !concealed {
+            let (_, added_from) = self.table.rivers.rob(claimee).expect("Tiles not found on river");
+            self.table.lands.melds.slice_mut()[index].set_addinfo(added_from);
         }
+        Phase::Replace{wind: claimee, expose: !concealed}.into()
     }
     pub fn ask(&mut self) -> Result<Step, failure::Error> {
+        self.table.wall.flush_dora();
         let claimee = self.table.rivers.last().expect("Tiles not found on river").discarded_by();
-        let mut claims = Claims::new(claimee);
-
-        for claimer in claimee.others() {
-            let seat = &mut self.seat(claimer);
-            seat.show_claim_phase();
-            let claim = seat.get_claim();
-            claims.add(claim, claimer);
+        let mut claims = self.collect_claims(claimee, false);
+        if self.rules.abortive_draws.triple_ron && claims.count(Claim::MAHJONG) == 3 {
+            return Finish::ThreeWinAbort.into()
         }
-
-        if let Some((claim, claimer)) = claims.next() {
+        let (winners, first) = self.take_winners(&mut claims);
+        if !winners.is_empty() {
+            return Finish::WinByDiscard(winners).into()
+        }
+        self.update_riichi(claimee);
+        self.status[claimee.id()].temporary_furiten = false;
+        if let Some(abort) = self.check_abort() {
+            return abort.into()
+        }
+        if let Some((claim, claimer)) = first {
             match claim {
-                Claim::MAHJONG => Finish::WinByDiscard(claimer).into(),
                 Claim::CHOW | Claim::PUNG | Claim::KONG => Phase::Meld{wind: claimer, claim}.into(),
-                _ => Phase::Draw{wind: claimee.rightside()}.into()
+                _ => Phase::Draw{wind: claimee.next_of(self.rules.seats)}.into()
             }
         } else {
-            Phase::Draw{wind: claimee.rightside()}.into()
+            Phase::Draw{wind: claimee.next_of(self.rules.seats)}.into()
         }
     }
-    pub fn discard(&mut self, seat: Wind) -> Result<Step, failure::Error> {
-        Phase::Ask.into()
+    pub fn update_riichi(&mut self, wind: Wind) {
+        let discarded = *self.table.rivers.last().expect("Tiles not found on river");
+        if discarded.is_riichi_declaration() {
+            let double_riichi = self.table.lands.melds.slice().is_empty()
+                && self.table.rivers.iter().filter(|d| d.discarded_by() == wind).count() == 1;
+            let status = &mut self.status[wind.id()];
+            status.riichi = true;
+            status.double_riichi = double_riichi;
+            status.ippatsu = true;
+            let pid = self.pid(wind);
+            self.sticks.score[pid] -= 1000;
+            self.sticks.deposit += 1000;
+            self.broadcast(&format!("{}家 {}", wind.show(), if double_riichi { "ダブル立直" } else { "立直" }));
+        } else {
+            self.status[wind.id()].ippatsu = false;
+        }
+    }
+    pub fn check_abort(&self) -> Option<Finish> {
+        let aborts = self.rules.abortive_draws;
+        let rivers = &self.table.rivers;
+        let melds = &self.table.lands.melds;
+        let figure = rivers.slice()[0].tile().figure();
+        if aborts.four_winds && self.rules.seats == 4 && rivers.len() == 4 && melds.slice().is_empty()
+            && figure.is_wind() && rivers.iter().all(|d| d.tile().figure() == figure) {
+            return Some(Finish::FourWindAbort)
+        }
+        let kongs: Vec<Option<Wind>> = melds.iter()
+            .filter(|m| m.set().shape() >= Shape::KONG)
+            .map(|m| m.wind(rivers))
+            .collect();
+        if aborts.four_kongs && kongs.len() == 4 && kongs.iter().any(|&w| w != kongs[0]) {
+            return Some(Finish::FourKongAbort)
+        }
+        if aborts.four_riichi && self.status.iter().all(|s| s.riichi) {
+            return Some(Finish::FourRiichiAbort)
+        }
+        None
+    }
+    pub fn break_ippatsu(&mut self) {
+        for status in self.status.iter_mut() {
+            status.ippatsu = false;
+        }
+    }
+    pub fn discard(&mut self, wind: Wind) -> Result<Step, failure::Error> {
+        let legal = self.legal_choices(wind, None);
+        let seat = &mut self.seat(wind);
+        seat.show_discard_phase();
+        loop {
+            let choice = seat.get_choice();
+            match seat.do_discard(choice, &legal) {
+                Ok(step) => return Ok(step),
+                Err(e) => {
+                    writeln!(seat.player, "{}", e);
+                }
+            }
+        }
     }
 }
 
@@ -474,9 +1359,460 @@ impl Phase {
             Meld{wind, claim} => state.meld(wind, claim),
             Ask => state.ask(),
             AskRobbingKong{index} => state.ask_robbing_kong(index),
+            North{wind} => state.north(wind),
             Discard{wind} => state.discard(wind),
         }
     }
 }
 
+#[cfg(test)]
+fn tiles(mpsz: &str) -> Tiles {
+    let mut figures = Figures::parse_mpsz(mpsz).unwrap();
+    let mut tiles = Tiles::new();
+    let mut spec = 0;
+    while let Some(f) = figures.next() {
+        tiles.add(Tile::from_id(4 * f.id() + spec % 4));
+        spec += 1;
+    }
+    tiles
+}
+
+// a dealt table with silent players, for building a State in tests
+#[cfg(test)]
+fn fixture(rules: &RuleSet) -> (Table, Sticks, Vec<Player>) {
+    let mut table = Table::new(rules);
+    init_table(&mut table, rules.seats);
+    (table, Sticks::new(rules), (0..rules.seats).map(|_| Player::new()).collect())
+}
+
+#[test]
+fn robbing_furiten_test() {
+    let rules = RuleSet::new();
+    let (table, sticks, players) = &mut fixture(&rules);
+    table.lands.tiles[Wind::SOUTH.id()] = tiles("23m456p789s11122z");
+    table.lands.tiles[Wind::EAST.id()] = tiles("1111m");
+    let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+    state.status[Wind::SOUTH.id()].riichi = true;
+    let kong = Set::from_shape_figure(Shape::KONG, Figure::parse("🀇").unwrap());
+    let ts = state.table.lands.tiles[Wind::EAST.id()].extract_set(kong).unwrap();
+    state.table.rivers.add(Wind::EAST, ts[0]);
+    let (_, i) = state.table.rivers.rob(Wind::EAST).unwrap();
+    state.table.lands.melds.add(Meld::from_set_robinfo(kong, i));
+    assert!(state.legal_claims(Wind::SOUTH, true).is_empty());
+    state.collect_claims(Wind::EAST, true);
+    assert!(!state.is_furiten(Wind::SOUTH));
+    state.table.rivers.add(Wind::WEST, tiles("4m").next().unwrap());
+    assert!(state.legal_claims(Wind::SOUTH, false).contains(&Claim::MAHJONG));
+}
+
+#[test]
+fn triple_ron_test() {
+    let rules = RuleSet::parse("multi_ron = triple\n[abortive_draws]\ntriple_ron = false").unwrap();
+    let (table, sticks, players) = &mut fixture(&rules);
+    for wind in Wind::EAST.others() {
+        table.lands.tiles[wind.id()] = tiles("23m456p789s11122z");
+    }
+    for player in players.iter_mut().skip(1) {
+        player.rx = Some(Box::new(Some(Ok("Mahjong".to_string())).into_iter()));
+    }
+    let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+    for wind in Wind::EAST.others() {
+        state.status[wind.id()].riichi = true;
+    }
+    state.table.rivers.add(Wind::EAST, tiles("4m").next().unwrap());
+    let winners = match state.ask().unwrap() {
+        Step::Finish(Finish::WinByDiscard(winners)) => winners,
+        _ => panic!("no triple ron"),
+    };
+    assert_eq!(winners.count(), 3);
+    Finish::WinByDiscard(winners).payment(state);
+    assert!(Wind::EAST.others().all(|w| state.sticks.score[w.id()] > rules.starting_points));
+    assert_eq!(state.sticks.score.iter().sum::<isize>(), 4 * rules.starting_points);
+}
+
+#[test]
+fn sanma_test() {
+    for &seats in [4, 3].iter() {
+        let rules = RuleSet { seats, uma: vec![0; seats], ..RuleSet::new() };
+        let (table, sticks, players) = &mut fixture(&rules);
+        table.lands.tiles[Wind::SOUTH.id()] = tiles("45p123s456s789s11z");
+        let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+        state.table.rivers.add(Wind::EAST, tiles("6p").next().unwrap());
+        assert_eq!(state.legal_claims(Wind::SOUTH, false).contains(&Claim::CHOW), seats == 4);
+    }
+    let rules = RuleSet { seats: 3, uma: vec![0; 3], ..RuleSet::new() };
+    let (table, sticks, players) = &mut fixture(&rules);
+    let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+    let north = Wind::NORTH.figure();
+    let seen = state.seat(Wind::EAST).visible_figures().get(north);
+    state.table.lands.norths[Wind::SOUTH.id()] = 2;
+    assert_eq!(state.seat(Wind::EAST).visible_figures().get(north), seen + 2);
+}
+
+#[test]
+fn settlement_test() {
+    let rules = RuleSet::new();
+    let start = rules.starting_points;
+    for &tsumo in [false, true].iter() {
+        let (table, sticks, players) = &mut fixture(&rules);
+        table.lands.tiles[Wind::SOUTH.id()] = tiles("12m44p555666777z");
+        sticks.stack = 2;
+        sticks.deposit = 2000;
+        let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+        state.table.rivers.add(Wind::SOUTH, tiles("9s").next().unwrap());
+        let winning = tiles("3m").next().unwrap();
+        let finish = if tsumo {
+            Finish::WinByDraw(Wind::SOUTH, winning)
+        } else {
+            state.table.rivers.add(Wind::EAST, winning);
+            let mut winners = Winds::new();
+            winners.add(Wind::SOUTH);
+            Finish::WinByDiscard(winners)
+        };
+        finish.payment(state);
+        let score = &state.sticks.score;
+        if tsumo {
+            assert_eq!(score, &[start - 16200, start + 34600, start - 8200, start - 8200]);
+            assert!(!state.sticks.is_bursted());
+        } else {
+            assert_eq!(score, &[start - 32600, start + 34600, start, start]);
+            assert_eq!(state.sticks.burst_flags, 1);
+        }
+        assert_eq!(state.sticks.deposit, 0);
+        assert_eq!(score.iter().sum::<isize>(), 4 * start + 2000);
+    }
+}
+
+#[test]
+fn meld_test() {
+    let rules = RuleSet::new();
+    let (table, sticks, players) = &mut fixture(&rules);
+    table.lands.tiles[Wind::SOUTH.id()] = tiles("4557p123s789s111z");
+    let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+    let (six, east) = (Figure::parse_mpsz("6p"), Figure::parse_mpsz("1z"));
+    let chows = |lo: &str| Set::from_shape_figure(Shape::CHOW, Figure::parse_mpsz(lo));
+    let land = state.table.lands.tiles[Wind::SOUTH.id()].clone();
+    assert_eq!(meld_options(&land, Wind::SOUTH, Claim::CHOW, six, Wind::EAST), vec![chows("4p"), chows("5p")]);
+    assert!(meld_options(&land, Wind::WEST, Claim::CHOW, six, Wind::EAST).is_empty());
+    assert!(meld_options(&land, Wind::SOUTH, Claim::PUNG, six, Wind::EAST).is_empty());
+    state.table.rivers.add(Wind::EAST, tiles("6p").next().unwrap());
+    let step = state.seat(Wind::SOUTH).make_meld(chows("4p"), six).unwrap();
+    assert!(matches!(step, Step::Phase(Phase::Discard{wind: Wind::SOUTH})));
+    assert_eq!(state.table.rivers.last().unwrap().robbed_by(), Some(Wind::SOUTH));
+    let meld = *state.table.lands.melds.last().unwrap();
+    assert_eq!((meld.wind(&state.table.rivers), meld.claimed()), (Some(Wind::SOUTH), 2));
+    assert!(!state.table.lands.tiles[Wind::SOUTH.id()].clone().figures().has_one(Figure::parse_mpsz("4p")));
+    state.table.rivers.add(Wind::NORTH, tiles("1z").next().unwrap());
+    let kongs = state.seat(Wind::SOUTH).meld_options(Claim::KONG, east, Wind::NORTH);
+    assert_eq!(kongs, vec![Set::from_shape_figure(Shape::KONG, east)]);
+    let step = state.seat(Wind::SOUTH).make_meld(kongs[0], east).unwrap();
+    assert!(matches!(step, Step::Phase(Phase::Replace{wind: Wind::SOUTH, expose: true})));
+}
 
+#[test]
+fn legal_claims_test() {
+    let rules = RuleSet::new();
+    let (table, sticks, players) = &mut fixture(&rules);
+    for wind in Wind::EAST.others() {
+        table.lands.tiles[wind.id()] = tiles("4557p123s789s111z");
+    }
+    players[1].rx = Some(Box::new(vec!["Pung", "Chow"].into_iter().map(|s| Ok(s.to_string()))));
+    players[2].rx = Some(Box::new(Some(Ok("Chow".to_string())).into_iter()));
+    let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+    state.table.rivers.add(Wind::EAST, tiles("6p").next().unwrap());
+    let legal = state.legal_claims(Wind::SOUTH, false);
+    assert_eq!(legal, vec![Claim::CHOW]);
+    for wind in [Wind::WEST, Wind::NORTH].iter() {
+        assert!(state.legal_claims(*wind, false).is_empty());
+    }
+    assert!(state.legal_claims(Wind::EAST, false).is_empty());
+    assert_eq!(state.seat(Wind::SOUTH).get_legal_claim(&legal), Claim::CHOW);
+    assert_eq!(state.seat(Wind::WEST).get_legal_claim(&[]), Claim::THROUGH);
+}
+
+#[test]
+fn legal_choices_test() {
+    let rules = RuleSet::new();
+    let (table, sticks, players) = &mut fixture(&rules);
+    table.lands.tiles[Wind::SOUTH.id()] = tiles("4557p123s789s111z");
+    let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+    let tsumogiri = Choice::DrawAndDiscard{riichi: false};
+    let discard = Choice::Discard{figure: Figure::parse_mpsz("7p"), red: false, riichi: false};
+    let drawn = state.legal_choices(Wind::SOUTH, Some(tiles("9m").next().unwrap()));
+    assert!(drawn.contains(&tsumogiri) && drawn.contains(&discard));
+    let legal = state.legal_choices(Wind::SOUTH, None);
+    assert!(!legal.contains(&tsumogiri) && legal.contains(&discard));
+    let seat = &mut state.seat(Wind::SOUTH);
+    assert!(seat.do_discard(tsumogiri, &drawn).is_err());
+    assert!(matches!(seat.do_discard(discard, &legal), Ok(Step::Phase(Phase::Ask))));
+    assert_eq!(seat.river.last().unwrap().tile().figure(), Figure::parse_mpsz("7p"));
+}
+
+#[test]
+fn riichi_test() {
+    let rules = RuleSet::new();
+    let (table, sticks, players) = &mut fixture(&rules);
+    table.lands.tiles[Wind::SOUTH.id()] = tiles("23m456p789s11122z");
+    table.lands.tiles[Wind::WEST.id()] = tiles("34599m123p456s77z");
+    let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+    let start = rules.starting_points;
+    let drawn = tiles("9m").next().unwrap();
+    let declare = Choice::DrawAndDiscard{riichi: true};
+    state.sticks.score[1] = 900;
+    assert!(!state.legal_choices(Wind::SOUTH, Some(drawn)).contains(&declare));
+    state.sticks.score[1] = start;
+    let legal = state.legal_choices(Wind::SOUTH, Some(drawn));
+    assert!(legal.contains(&declare));
+    assert!(!legal.contains(&Choice::Discard{figure: Figure::parse_mpsz("4p"), red: false, riichi: true}));
+    state.seat(Wind::SOUTH).do_choice(declare, drawn, &legal).unwrap();
+    state.update_riichi(Wind::SOUTH);
+    let status = state.status[Wind::SOUTH.id()];
+    assert!(status.riichi && status.double_riichi && status.ippatsu);
+    assert_eq!((state.sticks.score[1], state.sticks.deposit), (start - 1000, 1000));
+    assert_eq!(state.table.rivers.sideways(Wind::SOUTH), Some(0));
+    state.meld(Wind::WEST, Claim::PUNG).unwrap();
+    assert!(!state.status[Wind::SOUTH.id()].ippatsu);
+    assert_eq!(state.table.rivers.sideways(Wind::SOUTH), None);
+    state.table.rivers.add(Wind::WEST, tiles("7z").next().unwrap());
+    state.table.rivers.add(Wind::SOUTH, tiles("9p").next().unwrap());
+    assert_eq!(state.table.rivers.sideways(Wind::SOUTH), Some(2));
+    // the fourth copy, as the helper deals the first three into the land
+    let fourth = |figure: Figure| Some(Tile::from_id(4 * figure.id() + 3));
+    let east = Figure::parse_mpsz("1z");
+    assert!(state.legal_choices(Wind::SOUTH, fourth(east)).contains(&Choice::Kong{figure: east}));
+    state.table.lands.tiles[Wind::SOUTH.id()] = tiles("1112m456p789s222z");
+    let one = Figure::parse_mpsz("1m");
+    assert!(!state.legal_choices(Wind::SOUTH, fourth(one)).contains(&Choice::Kong{figure: one}));
+}
+
+#[test]
+fn furiten_test() {
+    let rules = RuleSet::new();
+    let tile = |mpsz: &str| tiles(mpsz).next().unwrap();
+    let ron = |state: &State| state.legal_claims(Wind::SOUTH, false).contains(&Claim::MAHJONG);
+    for &riichi in [false, true].iter() {
+        let (table, sticks, players) = &mut fixture(&rules);
+        table.lands.tiles[Wind::SOUTH.id()] = tiles("23m456p789s11122z");
+        let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+        state.status[Wind::SOUTH.id()].riichi = riichi;
+        state.table.rivers.add(Wind::EAST, tile("4m"));
+        assert!(ron(state));
+        state.table.rivers.add(Wind::WEST, tile("1m"));
+        state.collect_claims(Wind::WEST, false);
+        state.table.rivers.add(Wind::NORTH, tile("4m"));
+        assert!(!ron(state));
+        state.table.rivers.add(Wind::SOUTH, tile("9p"));
+        state.ask().unwrap();
+        state.table.rivers.add(Wind::WEST, tile("4m"));
+        assert_eq!(ron(state), !riichi);
+    }
+    let (table, sticks, players) = &mut fixture(&rules);
+    table.lands.tiles[Wind::SOUTH.id()] = tiles("23m456p789s11122z");
+    let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+    state.table.rivers.add(Wind::SOUTH, tile("1m"));
+    state.table.rivers.add(Wind::WEST, tile("4m"));
+    assert!(!ron(state));
+}
+
+#[test]
+fn abort_test() {
+    for &on in [true, false].iter() {
+        let aborts = AbortiveDraws { four_winds: on, four_kongs: on, four_riichi: on, triple_ron: on, nine_terminals: on };
+        let rules = RuleSet { abortive_draws: aborts, ..RuleSet::new() };
+        let (table, sticks, players) = &mut fixture(&rules);
+        table.lands.tiles[Wind::EAST.id()] = tiles("19m19p19s1234z234m");
+        for wind in Wind::EAST.others() {
+            table.lands.tiles[wind.id()] = tiles("23m456p789s11122z");
+        }
+        for player in players.iter_mut().skip(1) {
+            player.rx = Some(Box::new(Some(Ok("Mahjong".to_string())).into_iter()));
+        }
+        let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+        let nine = state.legal_choices(Wind::EAST, tiles("5m").next());
+        assert_eq!(nine.contains(&Choice::NineTerminals), on);
+        let mut norths = tiles("4444z");
+        for wind in Wind::make_iter() {
+            state.table.rivers.add(wind, norths.next().unwrap());
+        }
+        assert_eq!(state.check_abort(), if on { Some(Finish::FourWindAbort) } else { None });
+        let mut kongs = tiles("1234s");
+        for &wind in [Wind::EAST, Wind::EAST, Wind::EAST, Wind::SOUTH].iter() {
+            state.table.rivers.add(wind, kongs.next().unwrap());
+            let (tile, index) = state.table.rivers.rob(wind).unwrap();
+            state.table.lands.melds.add(Meld::from_set_robinfo(Set::from_shape_figure(Shape::KONG, tile.figure()), index));
+        }
+        assert_eq!(state.check_abort(), if on { Some(Finish::FourKongAbort) } else { None });
+        state.table.lands.melds.clear();
+        state.status = [Status { riichi: true, ..Status::default() }; 4];
+        assert_eq!(state.check_abort(), if on { Some(Finish::FourRiichiAbort) } else { None });
+        state.status = Default::default();
+        state.table.rivers.add(Wind::EAST, tiles("4m").next().unwrap());
+        match state.ask().unwrap() {
+            Step::Finish(Finish::ThreeWinAbort) => assert!(on),
+            Step::Finish(Finish::WinByDiscard(winners)) => assert!(!on && winners.count() == 2),
+            _ => panic!("no ron"),
+        }
+    }
+}
+
+#[test]
+fn exhaustive_draw_test() {
+    let rules = RuleSet::new();
+    let start = rules.starting_points;
+    for &(n, paid, received) in [(1, 1000, 3000), (2, 1500, 1500), (3, 3000, 1000)].iter() {
+        let (table, sticks, players) = &mut fixture(&rules);
+        let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+        let mut tenpai = Winds::new();
+        for wind in Wind::make_iter().take(n) {
+            tenpai.add(wind);
+        }
+        state.settle_draw(tenpai, Winds::new());
+        for wind in Wind::make_iter() {
+            let delta = if tenpai.contains(wind) { received } else { -paid };
+            assert_eq!(state.sticks.score[wind.id()], start + delta);
+        }
+        let finish = Finish::ExaustiveDraw{tenpai, nagashi: Winds::new()};
+        assert!(finish.has_bonus_hand() && finish.is_goulash_hand());
+    }
+    let mut south = Winds::new();
+    south.add(Wind::SOUTH);
+    assert!(!Finish::ExaustiveDraw{tenpai: south, nagashi: Winds::new()}.has_bonus_hand());
+    let (table, sticks, players) = &mut fixture(&rules);
+    let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+    let mut discards = tiles("19m9p1s");
+    for &wind in [Wind::SOUTH, Wind::SOUTH, Wind::WEST, Wind::WEST].iter() {
+        state.table.rivers.add(wind, discards.next().unwrap());
+    }
+    state.table.rivers.add(Wind::NORTH, tiles("2p").next().unwrap());
+    state.table.rivers.add(Wind::EAST, tiles("1z").next().unwrap());
+    state.table.rivers.rob(Wind::NORTH);
+    let nagashi = match state.exhaust() {
+        Finish::ExaustiveDraw{nagashi, ..} => nagashi,
+        _ => panic!("no exhaustive draw"),
+    };
+    assert_eq!((nagashi.count(), nagashi.contains(Wind::SOUTH), nagashi.contains(Wind::WEST)), (2, true, true));
+    state.settle_draw(Winds::new(), south);
+    assert_eq!(state.sticks.score, vec![start - 4000, start + 8000, start - 2000, start - 2000]);
+}
+
+#[test]
+fn next_hand_test() {
+    let rules = RuleSet::new();
+    let start = rules.starting_points;
+    let tile = tiles("1m").next().unwrap();
+    let seats = |winds: &[Wind]| {
+        let mut w = Winds::new();
+        for &wind in winds {
+            w.add(wind);
+        }
+        w
+    };
+    let draw = |tenpai: &[Wind]| Finish::ExaustiveDraw{tenpai: seats(tenpai), nagashi: Winds::new()};
+    let sticks = &mut Sticks::new(&rules);
+    sticks.stack = 1;
+    assert_eq!(next_hand(&rules, sticks, draw(&[Wind::EAST]), 0, 1, 2), Some((0, 1, 2)));
+    assert_eq!(next_hand(&rules, sticks, draw(&[Wind::SOUTH]), 0, 1, 2), Some((0, 2, 2)));
+    assert_eq!(next_hand(&rules, sticks, Finish::FourWindAbort, 0, 3, 2), Some((0, 3, 2)));
+    assert_eq!(next_hand(&rules, sticks, Finish::WinByDraw(Wind::SOUTH, tile), 0, 3, 2), Some((1, 0, 0)));
+    sticks.deposit = 2000;
+    sticks.score = vec![start - 1000, start - 1000, start + 2000, start - 2000];
+    assert_eq!(next_hand(&rules, sticks, draw(&[]), 1, 0, 2), Some((1, 1, 2)));
+    sticks.award_deposit();
+    assert_eq!((sticks.score[2], sticks.deposit), (start + 4000, 0));
+    sticks.score = vec![start - 10000, start, start, start + 10000];
+    let win = Finish::WinByDraw(Wind::EAST, tile);
+    assert_eq!(next_hand(&rules, sticks, win, 1, 3, 2), None);
+    assert_eq!(next_hand(&rules, sticks, draw(&[Wind::EAST]), 1, 3, 2), None);
+    assert_eq!(next_hand(&rules, sticks, Finish::FourRiichiAbort, 1, 3, 2), Some((1, 3, 2)));
+    sticks.score = vec![start + 5000, start - 1000, start - 2000, start - 2000];
+    assert_eq!(next_hand(&rules, sticks, win, 1, 3, 2), Some((1, 3, 2)));
+    sticks.score = vec![start; 4];
+    assert_eq!(next_hand(&rules, sticks, draw(&[]), 1, 3, 2), Some((2, 0, 2)));
+    assert_eq!(next_hand(&RuleSet { extension: false, ..RuleSet::new() }, sticks, draw(&[]), 1, 3, 2), None);
+    assert_eq!(next_hand(&rules, sticks, draw(&[]), 2, 3, 2), None);
+    assert_eq!(next_hand(&rules, sticks, draw(&[Wind::EAST]), 2, 0, 2), Some((2, 0, 2)));
+    sticks.score[1] = rules.target_points;
+    assert_eq!(next_hand(&rules, sticks, draw(&[]), 1, 3, 2), None);
+    assert_eq!(next_hand(&rules, sticks, draw(&[Wind::EAST]), 2, 0, 2), None);
+    sticks.score[1] = start;
+    sticks.burst_flags = 1;
+    assert_eq!(next_hand(&rules, sticks, draw(&[Wind::EAST]), 0, 0, 2), None);
+}
+
+#[test]
+fn pao_test() {
+    let rules = RuleSet { pao_four_kongs: true, ..RuleSet::new() };
+    let start = rules.starting_points;
+    // melds already made, the land before the fed meld, the fed figure, the shape and the yakuman total
+    let cases = [
+        ("56z", "77z123m4p9s", "7z", Shape::PUNG, 32000),
+        ("123z", "44z4p9s", "4z", Shape::PUNG, 64000),
+        ("123m", "111s4p", "1s", Shape::KONG, 32000),
+    ];
+    for &(melded, land, fed, shape, total) in cases.iter() {
+        for &tsumo in [true, false].iter() {
+            let (table, sticks, players) = &mut fixture(&rules);
+            table.lands.tiles[Wind::SOUTH.id()] = tiles(land);
+            sticks.stack = 1;
+            let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+            let mut figures = Figures::parse_mpsz(melded).unwrap();
+            while let Some(f) = figures.next() {
+                state.table.rivers.add(Wind::NORTH, Tile::from_id(4 * f.id() + 3));
+                let (_, i) = state.table.rivers.rob(Wind::SOUTH).unwrap();
+                state.table.lands.melds.add(Meld::from_set_robinfo(Set::from_shape_figure(shape, f), i));
+            }
+            let fed = Figure::parse_mpsz(fed);
+            state.table.rivers.add(Wind::WEST, Tile::from_id(4 * fed.id() + 3));
+            state.meld(Wind::SOUTH, if shape == Shape::KONG { Claim::KONG } else { Claim::PUNG }).unwrap();
+            if shape == Shape::PUNG {
+                let nine = Choice::Discard{figure: Figure::parse_mpsz("9s"), red: false, riichi: false};
+                let legal = state.legal_choices(Wind::SOUTH, None);
+                state.seat(Wind::SOUTH).do_discard(nine, &legal).unwrap();
+            }
+            assert_eq!(state.status[Wind::SOUTH.id()].pao.map(|(liable, _)| liable), Some(Wind::WEST));
+            let winning = Tile::from_id(4 * Figure::parse_mpsz("4p").id() + 3);
+            let finish = if tsumo {
+                Finish::WinByDraw(Wind::SOUTH, winning)
+            } else {
+                state.table.rivers.add(Wind::EAST, winning);
+                let mut winners = Winds::new();
+                winners.add(Wind::SOUTH);
+                Finish::WinByDiscard(winners)
+            };
+            finish.payment(state);
+            let expected = if tsumo {
+                [start, start + total + 300, start - total - 300, start]
+            } else {
+                [start - total / 2 - 300, start + total + 300, start - total / 2, start]
+            };
+            assert_eq!(state.sticks.score, expected);
+        }
+    }
+}
+
+#[test]
+fn kuikae_test() {
+    let cases = [
+        (Kuikae::None, "6p", 2, vec![], vec!["3p", "6p", "7p"]),
+        (Kuikae::SameTile, "6p", 2, vec!["6p"], vec!["3p", "7p"]),
+        (Kuikae::Suji, "6p", 2, vec!["3p", "6p"], vec!["7p"]),
+        (Kuikae::Suji, "3p", 0, vec!["3p", "6p"], vec!["7p"]),
+    ];
+    for &(kuikae, claimed, position, ref forbidden, ref allowed) in cases.iter() {
+        let rules = RuleSet { kuikae, ..RuleSet::new() };
+        let (table, sticks, players) = &mut fixture(&rules);
+        table.lands.tiles[Wind::SOUTH.id()] = tiles("34567p123s789s11z");
+        let state = &mut State::new(&rules, sticks, Wind::EAST, 0, table, players, Dice::new());
+        let claimed = Figure::parse_mpsz(claimed);
+        state.table.rivers.add(Wind::EAST, Tile::from_id(4 * claimed.id() + 3));
+        state.meld(Wind::SOUTH, Claim::CHOW).unwrap();
+        let meld = *state.table.lands.melds.last().unwrap();
+        assert_eq!(meld.set().figure().rank().id() + meld.claimed(), claimed.rank().id());
+        assert_eq!(meld.claimed(), position);
+        let legal = state.legal_choices(Wind::SOUTH, None);
+        let seat = state.seat(Wind::SOUTH);
+        let discard = |mpsz: &str| Choice::Discard{figure: Figure::parse_mpsz(mpsz), red: false, riichi: false};
+        assert!(forbidden.iter().all(|&f| seat.check_choice(discard(f), &legal).is_err()));
+        assert!(allowed.iter().all(|&f| seat.check_choice(discard(f), &legal).is_ok()));
+    }
+}
@@ -0,0 +1,479 @@
+use tile::*;
+use meld::*;
+use table::*;
+use shanten;
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Group {
+    pub set: Set,
+    pub concealed: bool,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Wait {
+    Ryanmen,
+    Kanchan,
+    Penchan,
+    Shanpon,
+    Tanki,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Form {
+    Standard,
+    SevenPairs,
+    ThirteenOrphans,
+    KnittedStraight,
+    Knitted,
+}
+
+// groups: concealed sets read from the hand, followed by the declared melds
+// completed: index of the group the winning figure completed, None for pair waits
+// singles: figures of a knitted form outside any set or pair; filling one of them is a Ryanmen wait
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Decomposition {
+    pub form: Form,
+    pub pairs: Vec<Figure>,
+    pub groups: Vec<Group>,
+    pub wait: Wait,
+    pub winning: Figure,
+    pub completed: Option<usize>,
+    pub singles: Vec<Figure>,
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Hand {
+    pub figures: Figures,
+    pub melds: Vec<Group>,
+}
+
+impl Group {
+    pub fn from_meld(meld: Meld, river: &Rivers) -> Self {
+        Group {
+            set: meld.set(),
+            concealed: meld.is_concealed(river),
+        }
+    }
+}
+
+impl Hand {
+    pub fn new(land: &Tiles, melds: &Melds, river: &Rivers, wind: Wind) -> Self {
+        Hand {
+            figures: land.clone().figures(),
+            melds: melds.iter_wind(river, wind)
+                .map(|(_, &m)| Group::from_meld(m, river))
+                .collect(),
+        }
+    }
+    #[cfg(test)]
+    pub fn parse_mpsz(s: &str) -> Self {
+        Hand { figures: Figures::parse_mpsz(s).unwrap(), melds: Vec::new() }
+    }
+    pub fn is_closed(&self) -> bool {
+        self.melds.iter().all(|g| g.concealed)
+    }
+    pub fn all_figures(&self, winning: Figure) -> Figures {
+        let mut figures = self.figures.clone();
+        figures.add(winning);
+        for g in self.melds.iter() {
+            figures.add_set(g.set);
+        }
+        figures
+    }
+    pub fn count_dora(&self, winning: Figure, doras: &[Figure]) -> usize {
+        let figures = self.all_figures(winning);
+        doras.iter().map(|&f| figures.get(f)).sum()
+    }
+    pub fn shanten(&self) -> isize {
+        shanten::shanten(&self.figures, self.melds.len())
+    }
+    pub fn is_mahjong(&self, winning: Figure) -> bool {
+        let mut figures = self.figures.clone();
+        figures.add(winning);
+        if figures.count() + 3 * self.melds.len() != 14 {
+            return false
+        }
+        is_standard(&figures) ||
+            (self.melds.is_empty() && (is_seven_pairs(&figures) || is_thirteen_orphans(&figures)))
+    }
+    pub fn decompose(&self, winning: Figure) -> Vec<Decomposition> {
+        let mut figures = self.figures.clone();
+        figures.add(winning);
+        let mut v = Vec::new();
+        if figures.count() + 3 * self.melds.len() != 14 {
+            return v
+        }
+        for (pair, sets) in standard_readings(&figures) {
+            let n = sets.len();
+            let groups: Vec<Group> = sets.into_iter()
+                .map(|set| Group { set, concealed: true })
+                .chain(self.melds.iter().cloned())
+                .collect();
+            let mut push = |wait, completed| {
+                let d = Decomposition {
+                    form: Form::Standard,
+                    pairs: vec![pair],
+                    groups: groups.clone(),
+                    wait,
+                    winning,
+                    completed,
+                    singles: Vec::new(),
+                };
+                if !v.contains(&d) {
+                    v.push(d)
+                }
+            };
+            if pair == winning {
+                push(Wait::Tanki, None)
+            }
+            for (i, g) in groups.iter().enumerate().take(n) {
+                if let Some(wait) = wait_shape(g.set, winning) {
+                    push(wait, Some(i))
+                }
+            }
+        }
+        if self.melds.is_empty() && is_seven_pairs(&figures) {
+            let mut pairs = Vec::new();
+            let mut fs = figures.clone();
+            while let Some(f) = fs.next() {
+                fs.del(f);
+                pairs.push(f);
+            }
+            v.push(Decomposition {
+                form: Form::SevenPairs,
+                pairs,
+                groups: Vec::new(),
+                wait: Wait::Tanki,
+                winning,
+                completed: None,
+                singles: Vec::new(),
+            })
+        }
+        if self.melds.is_empty() && is_thirteen_orphans(&figures) {
+            let pair = Figure::mk_iter().find(|&f| figures.get(f) == 2).expect("thirteen orphans without pair");
+            v.push(Decomposition {
+                form: Form::ThirteenOrphans,
+                pairs: vec![pair],
+                groups: Vec::new(),
+                wait: Wait::Tanki,
+                winning,
+                completed: None,
+                singles: Vec::new(),
+            })
+        }
+        v
+    }
+    // adds the hands only MCR accepts: seven pairs holding four of a kind, the knitted straight
+    // and the honors with knitted tiles
+    pub fn decompose_mcr(&self, winning: Figure) -> Vec<Decomposition> {
+        let mut v = self.decompose(winning);
+        let mut figures = self.figures.clone();
+        figures.add(winning);
+        if figures.count() + 3 * self.melds.len() != 14 {
+            return v
+        }
+        let closed = self.melds.is_empty();
+        if closed && !is_seven_pairs(&figures) && Suit::mk_iter().all(|s| figures.ranks(s).raw() & 0o111111111 == 0) {
+            let mut pairs = Vec::new();
+            let mut fs = figures.clone();
+            while let Some(f) = fs.next() {
+                fs.del(f);
+                pairs.push(f);
+            }
+            v.push(Decomposition {
+                form: Form::SevenPairs,
+                pairs,
+                groups: Vec::new(),
+                wait: Wait::Tanki,
+                winning,
+                completed: None,
+                singles: Vec::new(),
+            })
+        }
+        if closed && is_knitted(&figures) {
+            let mut singles = Vec::new();
+            let mut fs = figures.clone();
+            while let Some(f) = fs.next() {
+                singles.push(f);
+            }
+            v.push(Decomposition {
+                form: Form::Knitted,
+                pairs: Vec::new(),
+                groups: Vec::new(),
+                wait: Wait::Tanki,
+                winning,
+                completed: None,
+                singles,
+            })
+        }
+        for knitted in knitted_straights() {
+            let mut rest = figures.clone();
+            let mut singles = Vec::new();
+            let mut ks = knitted.clone();
+            while let Some(f) = ks.next() {
+                if rest.get(f) == 0 {
+                    break
+                }
+                rest.del(f);
+                singles.push(f);
+            }
+            if singles.len() != 9 {
+                continue
+            }
+            for (pair, sets) in standard_readings(&rest) {
+                let n = sets.len();
+                let groups: Vec<Group> = sets.into_iter()
+                    .map(|set| Group { set, concealed: true })
+                    .chain(self.melds.iter().cloned())
+                    .collect();
+                let mut push = |wait, completed| v.push(Decomposition {
+                    form: Form::KnittedStraight,
+                    pairs: vec![pair],
+                    groups: groups.clone(),
+                    wait,
+                    winning,
+                    completed,
+                    singles: singles.clone(),
+                });
+                if knitted.get(winning) > 0 {
+                    push(Wait::Ryanmen, None)
+                }
+                if pair == winning {
+                    push(Wait::Tanki, None)
+                }
+                for (i, g) in groups.iter().enumerate().take(n) {
+                    if let Some(wait) = wait_shape(g.set, winning) {
+                        push(wait, Some(i))
+                    }
+                }
+            }
+        }
+        v
+    }
+}
+
+impl Decomposition {
+    pub fn is_closed(&self) -> bool {
+        self.groups.iter().all(|g| g.concealed)
+    }
+    pub fn sets<'a>(&'a self) -> impl Iterator<Item = Set> + 'a {
+        self.groups.iter().map(|g| g.set)
+    }
+    pub fn figures(&self) -> Figures {
+        let mut figures = Figures::new();
+        for set in self.sets() {
+            figures.add_set(set);
+        }
+        for &pair in self.pairs.iter() {
+            figures.add(pair);
+            figures.add(pair);
+        }
+        for &single in self.singles.iter() {
+            figures.add(single);
+        }
+        if self.form == Form::ThirteenOrphans {
+            for suit in Suit::mk_iter() {
+                let mut ranks = orphans(suit);
+                while let Some(r) = ranks.next() {
+                    let f = Figure::from_suitrank(suit, r);
+                    if f != self.pairs[0] {
+                        figures.add(f);
+                    }
+                }
+            }
+        }
+        figures
+    }
+    pub fn is_concealed_set(&self, i: usize, tsumo: bool) -> bool {
+        self.groups[i].concealed && (tsumo || self.completed != Some(i))
+    }
+}
+
+pub fn wait_shape(set: Set, winning: Figure) -> Option<Wait> {
+    let figure = set.figure();
+    let mut ranks = set.ranks();
+    if figure.suit() != winning.suit() || !ranks.has(winning.rank()) || set.shape() > Shape::PUNG {
+        return None
+    }
+    ranks.del(winning.rank());
+    let partial = SuitRanks::from_suitranks(winning.suit(), ranks);
+    if partial.is_pair() {
+        Some(Wait::Shanpon)
+    } else if partial.is_kanchan() {
+        Some(Wait::Kanchan)
+    } else if partial.is_penchan() {
+        Some(Wait::Penchan)
+    } else if partial.is_ryanmen() {
+        Some(Wait::Ryanmen)
+    } else {
+        None
+    }
+}
+
+fn set_readings(suit: Suit, ranks: Ranks) -> Vec<Vec<Set>> {
+    let r = match ranks.lowest() {
+        Some(r) => r,
+        None => return vec![Vec::new()]
+    };
+    let figure = Figure::from_suitrank(suit, r);
+    let mut v = Vec::new();
+    let mut options = Vec::new();
+    if ranks.get(r) >= 3 {
+        options.push(Set::from_shape_figure(Shape::PUNG, figure));
+    }
+    if suit.is_numeric() && ranks.filter_chow().has(r) {
+        options.push(Set::from_shape_figure(Shape::CHOW, figure));
+    }
+    for set in options {
+        for mut rest in set_readings(suit, ranks.sub(set.ranks())) {
+            rest.insert(0, set);
+            v.push(rest);
+        }
+    }
+    v
+}
+
+fn suit_readings(suit: Suit, ranks: Ranks) -> Vec<(Option<Figure>, Vec<Set>)> {
+    match ranks.count() % 3 {
+        0 => set_readings(suit, ranks).into_iter().map(|sets| (None, sets)).collect(),
+        2 => {
+            let mut v = Vec::new();
+            let mut pairs = ranks.filter_pair();
+            while let Some(r) = pairs.next() {
+                let pair = Figure::from_suitrank(suit, r);
+                for sets in set_readings(suit, ranks.sub(Ranks::make_pair(r))) {
+                    v.push((Some(pair), sets))
+                }
+            }
+            v
+        },
+        _ => Vec::new()
+    }
+}
+
+fn standard_readings(figures: &Figures) -> Vec<(Figure, Vec<Set>)> {
+    let mut v: Vec<(Option<Figure>, Vec<Set>)> = vec![(None, Vec::new())];
+    for suit in Suit::mk_iter() {
+        let readings = suit_readings(suit, figures.ranks(suit));
+        v = v.iter().flat_map(|(p, sets)| readings.iter().filter_map(move |(q, more)| {
+            if p.is_some() && q.is_some() {
+                None
+            } else {
+                Some((p.or(*q), sets.iter().chain(more.iter()).cloned().collect()))
+            }
+        })).collect();
+    }
+    v.into_iter().filter_map(|(p, mut sets)| {
+        sets.sort_by_key(|s| (s.figure().id(), s.shape().id()));
+        p.map(|p| (p, sets))
+    }).collect()
+}
+
+pub fn orphans(suit: Suit) -> Ranks {
+    if suit.is_numeric() {
+        Ranks::from_raw(0o100000001)
+    } else {
+        Ranks::from_raw(0o1111111)
+    }
+}
+
+pub fn is_sets(ranks: Ranks, numeric: bool) -> bool {
+    if let Some(r) = ranks.lowest() {
+        (ranks.get(r) >= 3 && is_sets(ranks.sub(Ranks::make_pung(r)), numeric)) ||
+            (numeric && ranks.filter_chow().has(r) && is_sets(ranks.sub(Ranks::make_chow(r)), numeric))
+    } else {
+        true
+    }
+}
+
+pub fn is_sets_pair(ranks: Ranks, numeric: bool) -> bool {
+    let mut pairs = ranks.filter_pair();
+    while let Some(r) = pairs.next() {
+        if is_sets(ranks.sub(Ranks::make_pair(r)), numeric) {
+            return true
+        }
+    }
+    false
+}
+
+pub fn is_standard(figures: &Figures) -> bool {
+    let mut pair = false;
+    Suit::mk_iter().all(|suit| {
+        let ranks = figures.ranks(suit);
+        match ranks.count() % 3 {
+            0 => is_sets(ranks, suit.is_numeric()),
+            2 if !pair => {
+                pair = true;
+                is_sets_pair(ranks, suit.is_numeric())
+            },
+            _ => false
+        }
+    }) && pair
+}
+
+pub fn is_seven_pairs(figures: &Figures) -> bool {
+    figures.count() == 14 && Suit::mk_iter().all(|suit| {
+        let ranks = figures.ranks(suit);
+        ranks.raw() == 2 * ranks.filter_pair().raw()
+    })
+}
+
+// 147, 258 and 369 spread over the three suits in each of the six ways
+pub fn knitted_straights() -> Vec<Figures> {
+    let suits = [Suit::CHARA, Suit::BAMBOO, Suit::CIRCLE];
+    let orders = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+    orders.iter().map(|order| {
+        let mut figures = Figures::new();
+        for (offset, &i) in order.iter().enumerate() {
+            for r in [offset, offset + 3, offset + 6].iter() {
+                figures.add(Figure::from_suitrank(suits[i], Rank::from_id(*r)));
+            }
+        }
+        figures
+    }).collect()
+}
+
+// fourteen different tiles, the numbers taken from a single knitted straight
+pub fn is_knitted(figures: &Figures) -> bool {
+    let mut fs = figures.clone();
+    let mut numbers = Vec::new();
+    while let Some(f) = fs.next() {
+        if figures.get(f) != 1 {
+            return false
+        }
+        if f.suit().is_numeric() {
+            numbers.push(f);
+        }
+    }
+    figures.count() == 14 && knitted_straights().iter().any(|k| numbers.iter().all(|&f| k.get(f) > 0))
+}
+
+pub fn is_thirteen_orphans(figures: &Figures) -> bool {
+    figures.count() == 14 && Suit::mk_iter().all(|suit| {
+        figures.ranks(suit).filter_one() == orphans(suit)
+    })
+}
+
+#[test]
+fn mahjong_test() {
+    assert!(Hand::parse_mpsz("123m456p789s1122z").is_mahjong(Figure::parse_mpsz("2z")));
+    assert!(Hand::parse_mpsz("1112345678999m").is_mahjong(Figure::parse_mpsz("5m")));
+    assert!(!Hand::parse_mpsz("123m456p789s1123z").is_mahjong(Figure::parse_mpsz("4z")));
+    assert!(Hand::parse_mpsz("1133m5577p99s116z").is_mahjong(Figure::parse_mpsz("6z")));
+    assert!(!Hand::parse_mpsz("1133m5577p99s111z").is_mahjong(Figure::parse_mpsz("1z")));
+    assert!(Hand::parse_mpsz("19m19p19s1234567z").is_mahjong(Figure::parse_mpsz("1m")));
+    assert!(!Hand::parse_mpsz("19m19p19s1234567z").is_mahjong(Figure::parse_mpsz("2m")));
+}
+
+#[test]
+fn decompose_test() {
+    let waits = |h: &str, w: &str| Hand::parse_mpsz(h).decompose(Figure::parse_mpsz(w)).iter().map(|d| d.wait).collect::<Vec<_>>();
+    assert_eq!(waits("3334m456p789s555z", "2m"), vec![Wait::Ryanmen]);
+    assert_eq!(waits("3334m456p789s555z", "4m"), vec![Wait::Tanki]);
+    assert_eq!(waits("12m456p789s11555z", "3m"), vec![Wait::Penchan]);
+    assert_eq!(waits("13m456p789s11555z", "2m"), vec![Wait::Kanchan]);
+    assert_eq!(waits("456m456p789s5566z", "5z"), vec![Wait::Shanpon]);
+    assert_eq!(waits("1112345678999m", "5m"), vec![Wait::Tanki]);
+    assert_eq!(waits("1112345678999m", "1m"), vec![Wait::Ryanmen, Wait::Shanpon]);
+    assert_eq!(Hand::parse_mpsz("1133m5577p99s116z").decompose(Figure::parse_mpsz("6z"))[0].form, Form::SevenPairs);
+}
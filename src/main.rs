@@ -8,14 +8,23 @@ mod game;
 mod action;
 mod player;
 mod dice;
+mod hand;
+mod shanten;
+mod yaku;
+mod fan;
+mod fu;
+mod rule;
+mod points;
 
 fn main() -> Result<(), failure::Error> {
-    let mut players = game::Players([
-        player::Player::from_stdio()?,
-        player::Player::from_stdio()?,
-        player::Player::from_stdio()?,
-        player::Player::from_stdio()?]);
-    let score = players.run_halfmatch()?;
+    let rules = match std::env::args().nth(1) {
+        Some(path) => rule::RuleSet::load(&path)?,
+        None => rule::RuleSet::new(),
+    };
+    let players = (0..rules.seats).map(|_| player::Player::from_stdio()).collect::<Result<Vec<_>, _>>()?;
+    let mut players = game::Players(players);
+    let score = players.run(&rules)?;
     println!("{:?}", score);
+    println!("{:?}", points::final_scores(&rules, &score));
     Ok(())
 }
\ No newline at end of file
@@ -23,15 +23,48 @@ impl Meld {
     pub fn wind(self, river: &Rivers) -> Option<Wind> {
         river.get(self.robbed_from())?.robbed_by()
     }
+    pub fn is_concealed(self, river: &Rivers) -> bool {
+        river.get(self.robbed_from())
+            .is_some_and(|d| d.robbed_by() == Some(d.discarded_by()))
+    }
     pub fn robbed_from(self) -> usize {
         (self.raw() & 0xff00) >> 8
     }
     pub fn added_from(self) -> usize {
         (self.raw() & 0xff0000) >> 16
     }
+    pub fn reds(self) -> usize {
+        (self.raw() >> 26) & 0o7
+    }
+    pub fn set_reds(&mut self, reds: usize) {
+        let raw = self.raw() & !(0o7 << 26);
+        *self = Self::from_raw(raw | (reds & 0o7) << 26)
+    }
+    // position of the claimed tile in a chow, counted from its lowest figure
+    pub fn claimed(self) -> usize {
+        (self.raw() >> 24) & 0o3
+    }
+    pub fn set_claimed(&mut self, position: usize) {
+        let raw = self.raw() & !(0o3 << 24);
+        *self = Self::from_raw(raw | (position & 0o3) << 24)
+    }
     pub fn set_addinfo(&mut self, added_from: usize) {
-        let mut raw = self.raw();
-        raw |= added_from << 16;
+        let set = Set::from_shape_figure(Shape::ADDED_KONG, self.set().figure());
+        let mut raw = self.raw() & !0xff00ff;
+        raw |= set.raw() | (added_from << 16);
         *self = Self::from_raw(raw)
     }
-}
\ No newline at end of file
+}
+#[test]
+fn added_kong_test() {
+    let figure = Figure::from_id(4);
+    let mut meld = Meld::from_set_robinfo(Set::from_shape_figure(Shape::PUNG, figure), 12);
+    meld.set_reds(1);
+    meld.set_claimed(2);
+    meld.set_addinfo(30);
+    assert_eq!(meld.set(), Set::from_shape_figure(Shape::ADDED_KONG, figure));
+    assert_eq!(meld.robbed_from(), 12);
+    assert_eq!(meld.added_from(), 30);
+    assert_eq!(meld.reds(), 1);
+    assert_eq!(meld.claimed(), 2);
+}
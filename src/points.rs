@@ -0,0 +1,158 @@
+use table::*;
+use rule::*;
+use tile::*;
+use hand::*;
+use yaku::*;
+use yaku;
+use fu;
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Limit {
+    Plain,
+    Mangan,
+    Haneman,
+    Baiman,
+    Sanbaiman,
+    Kazoe,
+    Yakuman(usize),
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Score {
+    pub han: usize,
+    pub fu: usize,
+    pub yakuman: usize,
+}
+
+impl Limit {
+    pub fn show(self) -> &'static str {
+        match self {
+            Limit::Plain => "",
+            Limit::Mangan => "満貫",
+            Limit::Haneman => "跳満",
+            Limit::Baiman => "倍満",
+            Limit::Sanbaiman => "三倍満",
+            Limit::Kazoe => "数え役満",
+            Limit::Yakuman(1) => "役満",
+            Limit::Yakuman(_) => "複合役満",
+        }
+    }
+}
+
+pub fn round_up(points: usize) -> usize {
+    points.div_ceil(100) * 100
+}
+
+impl Score {
+    pub fn limit(&self, rules: &RuleSet) -> Limit {
+        if self.yakuman > 0 {
+            return Limit::Yakuman(if rules.multiple_yakuman { self.yakuman } else { 1 })
+        }
+        match self.han {
+            0..=4 => {
+                let base = self.fu << (self.han + 2);
+                if base >= 2000 || (rules.kiriage && base >= 1920) {
+                    Limit::Mangan
+                } else {
+                    Limit::Plain
+                }
+            },
+            5 => Limit::Mangan,
+            6..=7 => Limit::Haneman,
+            8..=10 => Limit::Baiman,
+            11..=12 => Limit::Sanbaiman,
+            _ => if rules.kazoe_yakuman { Limit::Kazoe } else { Limit::Sanbaiman },
+        }
+    }
+    pub fn base(&self, rules: &RuleSet) -> usize {
+        match self.limit(rules) {
+            Limit::Plain => self.fu << (self.han + 2),
+            Limit::Mangan => 2000,
+            Limit::Haneman => 3000,
+            Limit::Baiman => 4000,
+            Limit::Sanbaiman => 6000,
+            Limit::Kazoe => 8000,
+            Limit::Yakuman(n) => 8000 * n,
+        }
+    }
+    // discarder None means a win by draw
+    pub fn payments(&self, rules: &RuleSet, winner: Wind, discarder: Option<Wind>) -> Vec<(Wind, isize)> {
+        let base = self.base(rules);
+        let dealer = winner == Wind::EAST;
+        match discarder {
+            Some(payer) => vec![(payer, round_up(base * if dealer { 6 } else { 4 }) as isize)],
+            None => {
+                let north = if rules.seats == 3 && rules.sanma_payment == SanmaPayment::NorthBisection {
+                    round_up(if dealer { 2 * base } else { base }) / 2
+                } else {
+                    0
+                };
+                winner.others_of(rules.seats).map(|payer| {
+                    let points = if dealer || payer == Wind::EAST { 2 * base } else { base };
+                    (payer, round_up(round_up(points) + north) as isize)
+                }).collect()
+            }
+        }
+    }
+}
+
+// final standings after uma, with the oka going to the top; ties are broken by seating order
+pub fn final_scores(rules: &RuleSet, score: &[isize]) -> Vec<isize> {
+    let mut order: Vec<usize> = (0..score.len()).collect();
+    order.sort_by_key(|&i| (-score[i], i));
+    let oka = (rules.return_points - rules.starting_points) * score.len() as isize;
+    let mut result = vec![0; score.len()];
+    for (rank, &i) in order.iter().enumerate() {
+        result[i] = score[i] - rules.return_points + rules.uma[rank] + if rank == 0 { oka } else { 0 };
+    }
+    result
+}
+
+// MCR: every other seat pays 8, the discarder or, on a self-drawn win, everyone adds the fan total
+pub fn fan_payments(fan: usize, winner: Wind, discarder: Option<Wind>, seats: usize) -> Vec<(Wind, isize)> {
+    winner.others_of(seats).map(|payer| {
+        let base = if discarder.is_none_or(|d| d == payer) { fan } else { 0 };
+        (payer, (base + 8) as isize)
+    }).collect()
+}
+
+pub fn best(hand: &Hand, winning: Figure, ctx: &Context, rules: &RuleSet) -> Option<(Score, Vec<(Yaku, usize)>)> {
+    hand.decompose(winning).iter().filter_map(|d| {
+        let yakus = yaku::evaluate(d, ctx);
+        if yakus.is_empty() {
+            return None
+        }
+        let score = Score {
+            han: yaku::han(&yakus),
+            fu: fu::fu(d, ctx),
+            yakuman: yaku::yakuman_count(&yakus),
+        };
+        Some((score, yakus))
+    }).max_by_key(|(score, _)| (score.base(rules), score.han, score.fu))
+}
+
+#[test]
+fn points_test() {
+    let rules = RuleSet::new();
+    let score = |han, fu| Score { han, fu, yakuman: 0 };
+    let total = |v: Vec<(Wind, isize)>| v.iter().map(|&(_, p)| p).sum::<isize>();
+    assert_eq!(score(1, 30).payments(&rules, Wind::SOUTH, Some(Wind::EAST)), vec![(Wind::EAST, 1000)]);
+    assert_eq!(total(score(4, 30).payments(&rules, Wind::SOUTH, Some(Wind::EAST))), 7700);
+    assert_eq!(total(score(4, 30).payments(&RuleSet { kiriage: true, ..RuleSet::new() }, Wind::SOUTH, Some(Wind::EAST))), 8000);
+    assert_eq!(score(2, 30).payments(&rules, Wind::EAST, None), vec![(Wind::SOUTH, 1000), (Wind::WEST, 1000), (Wind::NORTH, 1000)]);
+    assert_eq!(score(1, 30).payments(&rules, Wind::WEST, None), vec![(Wind::NORTH, 300), (Wind::EAST, 500), (Wind::SOUTH, 300)]);
+    assert_eq!(total(score(2, 20).payments(&rules, Wind::WEST, None)), 1500);
+    assert_eq!(total(score(13, 30).payments(&rules, Wind::SOUTH, Some(Wind::EAST))), 32000);
+    assert_eq!(total(Score { han: 0, fu: 0, yakuman: 2 }.payments(&rules, Wind::EAST, Some(Wind::SOUTH))), 96000);
+    assert_eq!(final_scores(&rules, &[25000, 40000, 10000, 25000]), [5000, 50000, -40000, -15000]);
+    let sanma = RuleSet { seats: 3, sanma_payment: SanmaPayment::NorthBisection, ..RuleSet::new() };
+    assert_eq!(score(1, 30).payments(&sanma, Wind::WEST, None), vec![(Wind::EAST, 700), (Wind::SOUTH, 500)]);
+    assert_eq!(total(score(5, 30).payments(&sanma, Wind::EAST, None)), 12000);
+    let tsumo_loss = RuleSet { seats: 3, ..RuleSet::new() };
+    assert_eq!(score(1, 30).payments(&tsumo_loss, Wind::WEST, None), vec![(Wind::EAST, 500), (Wind::SOUTH, 300)]);
+    assert_eq!(total(score(5, 30).payments(&tsumo_loss, Wind::EAST, None)), 8000);
+    let sanma_match = RuleSet { seats: 3, starting_points: 35000, return_points: 40000, uma: vec![15000, 0, -15000], ..RuleSet::new() };
+    assert_eq!(final_scores(&sanma_match, &[35000, 50000, 20000]), [-5000, 40000, -35000]);
+    assert_eq!(fan_payments(10, Wind::SOUTH, Some(Wind::EAST), 4), vec![(Wind::WEST, 8), (Wind::NORTH, 8), (Wind::EAST, 18)]);
+    assert_eq!(total(fan_payments(10, Wind::SOUTH, None, 4)), 54);
+}
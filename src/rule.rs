@@ -0,0 +1,340 @@
+use tile::*;
+use table::Wall;
+use failure;
+use std;
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Style {
+    Riichi,
+    Mcr,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Length {
+    East,
+    Half,
+    Full,
+}
+
+impl Length {
+    pub fn rounds(self) -> usize {
+        match self {
+            Length::East => 1,
+            Length::Half => 2,
+            Length::Full => 4,
+        }
+    }
+}
+
+// who covers the absent north seat's share of a win by draw in three-player games
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum SanmaPayment {
+    TsumoLoss,
+    NorthBisection,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum MultiRon {
+    HeadBump,
+    Double,
+    Triple,
+}
+
+impl MultiRon {
+    pub fn max_winners(self) -> usize {
+        match self {
+            MultiRon::HeadBump => 1,
+            MultiRon::Double => 2,
+            MultiRon::Triple => 3,
+        }
+    }
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Kuikae {
+    None,
+    SameTile,
+    Suji,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct AbortiveDraws {
+    pub four_winds: bool,
+    pub four_kongs: bool,
+    pub four_riichi: bool,
+    pub triple_ron: bool,
+    pub nine_terminals: bool,
+}
+
+impl AbortiveDraws {
+    pub fn new() -> Self {
+        AbortiveDraws {
+            four_winds: true,
+            four_kongs: true,
+            four_riichi: true,
+            triple_ron: true,
+            nine_terminals: true,
+        }
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct RuleSet {
+    pub style: Style,
+    pub seats: usize,
+    pub sanma_payment: SanmaPayment,
+    pub length: Length,
+    pub dead_wall: usize,
+    pub starting_points: isize,
+    pub return_points: isize,
+    pub target_points: isize,
+    pub uma: Vec<isize>,
+    pub busting: bool,
+    pub open_tanyao: bool,
+    pub agari_yame: bool,
+    pub extension: bool,
+    pub kiriage: bool,
+    pub kazoe_yakuman: bool,
+    pub multiple_yakuman: bool,
+    pub double_yakuman: bool,
+    pub kan_dora_after_discard: bool,
+    pub ura_dora: bool,
+    pub kokushi_robs_concealed_kong: bool,
+    pub multi_ron: MultiRon,
+    pub declare_tenpai: bool,
+    pub pao_four_kongs: bool,
+    pub kuikae: Kuikae,
+    pub nagashi_mangan: bool,
+    pub abortive_draws: AbortiveDraws,
+    pub red_fives: RedFives,
+}
+
+impl RuleSet {
+    pub fn new() -> Self {
+        RuleSet {
+            style: Style::Riichi,
+            seats: 4,
+            sanma_payment: SanmaPayment::TsumoLoss,
+            length: Length::Half,
+            dead_wall: Wall::N_DEAD_WALL,
+            starting_points: 25000,
+            return_points: 30000,
+            target_points: 30000,
+            uma: vec![20000, 10000, -10000, -20000],
+            busting: true,
+            open_tanyao: true,
+            agari_yame: true,
+            extension: true,
+            kiriage: false,
+            kazoe_yakuman: true,
+            multiple_yakuman: true,
+            double_yakuman: true,
+            kan_dora_after_discard: true,
+            ura_dora: true,
+            kokushi_robs_concealed_kong: true,
+            multi_ron: MultiRon::Double,
+            declare_tenpai: false,
+            pao_four_kongs: false,
+            kuikae: Kuikae::Suji,
+            nagashi_mangan: true,
+            abortive_draws: AbortiveDraws::new(),
+            red_fives: {
+                let mut reds = RedFives::new();
+                for suit in Suit::mk_iter().filter(|s| s.is_numeric()) {
+                    reds.add(suit, 0);
+                }
+                reds
+            },
+        }
+    }
+    // Mahjong Competition Rules: sixteen hands from zero, the dealer passing on every hand
+    pub fn mcr() -> Self {
+        RuleSet {
+            style: Style::Mcr,
+            length: Length::Full,
+            starting_points: 0,
+            return_points: 0,
+            target_points: 0,
+            uma: vec![0; 4],
+            busting: false,
+            agari_yame: false,
+            extension: false,
+            kokushi_robs_concealed_kong: false,
+            multi_ron: MultiRon::HeadBump,
+            kuikae: Kuikae::None,
+            nagashi_mangan: false,
+            abortive_draws: AbortiveDraws {
+                four_winds: false,
+                four_kongs: false,
+                four_riichi: false,
+                triple_ron: false,
+                nine_terminals: false,
+            },
+            red_fives: RedFives::new(),
+            ..RuleSet::new()
+        }
+    }
+}
+
+impl RuleSet {
+    pub fn load(path: &str) -> Result<Self, failure::Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+    // reads `key = value` lines; a `[section]` header prefixes the following keys with `section.`
+    pub fn parse(s: &str) -> Result<Self, failure::Error> {
+        let mut rules = RuleSet::new();
+        let mut section = String::new();
+        let mut first = true;
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = format!("{}.", line[1..line.len()-1].trim());
+                continue
+            }
+            let mut kv = line.splitn(2, '=');
+            let key = format!("{}{}", section, kv.next().unwrap_or("").trim());
+            let value = kv.next()
+                .ok_or(failure::err_msg(format!("No value for {}", key)))?
+                .trim().trim_matches('"');
+            if key == "style" && !first {
+                return Err(failure::err_msg("style has to come before the other rules"))
+            }
+            rules.set(&key, value)?;
+            first = false;
+        }
+        if rules.uma.len() != rules.seats {
+            return Err(failure::err_msg("uma needs one entry per seat"))
+        }
+        // three rons would abort before a third winner is ever taken
+        if rules.multi_ron == MultiRon::Triple && rules.abortive_draws.triple_ron {
+            return Err(failure::err_msg("multi_ron = triple needs abortive_draws.triple_ron = false"))
+        }
+        Ok(rules)
+    }
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), failure::Error> {
+        let invalid = || failure::err_msg(format!("Invalid value for {}: {}", key, value));
+        let flag = || value.parse::<bool>().map_err(|_| invalid());
+        let points = || value.parse::<isize>().map_err(|_| invalid());
+        let list = || value.trim_matches(|c| c == '[' || c == ']').split(',')
+            .map(|v| v.trim().parse::<isize>().map_err(|_| invalid()))
+            .collect::<Result<Vec<isize>, failure::Error>>();
+        match key {
+            // resets the other rules to the style's defaults, so parse only takes it first
+            "style" => *self = match value {
+                "riichi" => RuleSet::new(),
+                "mcr" => RuleSet::mcr(),
+                _ => return Err(invalid())
+            },
+            "seats" => self.seats = match value {
+                "3" if self.style == Style::Riichi => 3,
+                "4" => 4,
+                _ => return Err(invalid())
+            },
+            "sanma_payment" => self.sanma_payment = match value {
+                "tsumo_loss" => SanmaPayment::TsumoLoss,
+                "north_bisection" => SanmaPayment::NorthBisection,
+                _ => return Err(invalid())
+            },
+            "length" => self.length = match value {
+                "east" => Length::East,
+                "half" => Length::Half,
+                "full" => Length::Full,
+                _ => return Err(invalid())
+            },
+            // the dora indicators have to stay in the dead wall
+            "dead_wall" => self.dead_wall = match value.parse::<usize>() {
+                Ok(n) if n >= 2 * Wall::N_INDICATOR => n,
+                _ => return Err(invalid())
+            },
+            "starting_points" => self.starting_points = points()?,
+            "return_points" => self.return_points = points()?,
+            "target_points" => self.target_points = points()?,
+            "uma" => self.uma = list()?,
+            "busting" => self.busting = flag()?,
+            "open_tanyao" => self.open_tanyao = flag()?,
+            "agari_yame" => self.agari_yame = flag()?,
+            "extension" => self.extension = flag()?,
+            "kiriage" => self.kiriage = flag()?,
+            "kazoe_yakuman" => self.kazoe_yakuman = flag()?,
+            "multiple_yakuman" => self.multiple_yakuman = flag()?,
+            "double_yakuman" => self.double_yakuman = flag()?,
+            "kan_dora_after_discard" => self.kan_dora_after_discard = flag()?,
+            "ura_dora" => self.ura_dora = flag()?,
+            "kokushi_robs_concealed_kong" => self.kokushi_robs_concealed_kong = flag()?,
+            "multi_ron" => self.multi_ron = match value {
+                "head_bump" => MultiRon::HeadBump,
+                "double" => MultiRon::Double,
+                "triple" => MultiRon::Triple,
+                _ => return Err(invalid())
+            },
+            "declare_tenpai" => self.declare_tenpai = flag()?,
+            "pao_four_kongs" => self.pao_four_kongs = flag()?,
+            "kuikae" => self.kuikae = match value {
+                "none" => Kuikae::None,
+                "same_tile" => Kuikae::SameTile,
+                "suji" => Kuikae::Suji,
+                _ => return Err(invalid())
+            },
+            "nagashi_mangan" => self.nagashi_mangan = flag()?,
+            "abortive_draws.four_winds" => self.abortive_draws.four_winds = flag()?,
+            "abortive_draws.four_kongs" => self.abortive_draws.four_kongs = flag()?,
+            "abortive_draws.four_riichi" => self.abortive_draws.four_riichi = flag()?,
+            "abortive_draws.triple_ron" => self.abortive_draws.triple_ron = flag()?,
+            "abortive_draws.nine_terminals" => self.abortive_draws.nine_terminals = flag()?,
+            "red_fives" => {
+                let counts = list()?;
+                let suits: Vec<Suit> = Suit::mk_iter().filter(|s| s.is_numeric()).collect();
+                if counts.len() != suits.len() || counts.iter().any(|n| !(0..=4).contains(n)) {
+                    return Err(invalid())
+                }
+                self.red_fives = RedFives::new();
+                for (&suit, &n) in suits.iter().zip(counts.iter()) {
+                    for spec in 0..n as usize {
+                        self.red_fives.add(suit, spec);
+                    }
+                }
+            },
+            _ => return Err(failure::err_msg(format!("Unknown rule: {}", key)))
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn parse_test() {
+    let rules = RuleSet::parse("
+        # club preset
+        length = \"east\"
+        starting_points = 30000
+        uma = [30000, 0, -30000]
+        open_tanyao = false
+        multi_ron = head_bump
+        seats = 3
+        red_fives = [0, 1, 2]
+
+        [abortive_draws]
+        four_winds = false
+    ").unwrap();
+    assert_eq!(rules.length, Length::East);
+    assert_eq!(rules.starting_points, 30000);
+    assert_eq!(rules.uma, [30000, 0, -30000]);
+    assert!(!rules.open_tanyao);
+    assert_eq!(rules.multi_ron, MultiRon::HeadBump);
+    assert_eq!(rules.seats, 3);
+    assert_eq!(rules.red_fives.count_in(Suit::CHARA), 0);
+    assert_eq!(rules.red_fives.count_in(Suit::CIRCLE), 2);
+    assert!(!rules.abortive_draws.four_winds);
+    assert!(rules.abortive_draws.four_kongs);
+    assert_eq!(RuleSet::parse("style = mcr\nlength = half").unwrap(), RuleSet { length: Length::Half, ..RuleSet::mcr() });
+    assert!(RuleSet::parse("style = mcr\nseats = 3").is_err());
+    assert!(RuleSet::parse("length = east\nstyle = mcr").is_err());
+    assert_eq!(RuleSet::parse("dead_wall = 18").unwrap().dead_wall, 18);
+    assert!(RuleSet::parse("dead_wall = 4").is_err());
+    assert!(RuleSet::parse("multi_ron = triple").is_err());
+    assert_eq!(RuleSet::parse("multi_ron = triple\n[abortive_draws]\ntriple_ron = false").unwrap().multi_ron, MultiRon::Triple);
+    assert!(RuleSet::parse("kuikae = sometimes").is_err());
+    assert!(RuleSet::parse("no_such_rule = true").is_err());
+}
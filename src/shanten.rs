@@ -0,0 +1,222 @@
+use tile::*;
+use hand::orphans;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+
+// [pair][sets] -> the most partial sets a suit can hold, -1 if unreachable
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+struct Blocks([[i8; 5]; 2]);
+
+thread_local! {
+    static TABLE: RefCell<HashMap<usize, Blocks>> = RefCell::new(HashMap::new());
+}
+
+impl Blocks {
+    fn new() -> Self {
+        Blocks([[-1; 5]; 2])
+    }
+    fn empty() -> Self {
+        let mut b = Self::new();
+        (b.0)[0][0] = 0;
+        b
+    }
+    fn merge(&mut self, other: &Self, pair: usize, sets: usize, partials: i8) {
+        for p in 0..2 - pair {
+            for m in 0..5 - sets {
+                let t = (other.0)[p][m];
+                if t >= 0 {
+                    let e = &mut (self.0)[p + pair][m + sets];
+                    *e = cmp::max(*e, t + partials);
+                }
+            }
+        }
+    }
+    fn combine(&self, other: &Self) -> Self {
+        let mut b = Self::new();
+        for p in 0..2 {
+            for m in 0..5 {
+                let t = (self.0)[p][m];
+                if t >= 0 {
+                    b.merge(other, p, m, t);
+                }
+            }
+        }
+        b
+    }
+}
+
+fn blocks(ranks: Ranks, numeric: bool) -> Blocks {
+    let key = ranks.raw() | (numeric as usize) << 27;
+    if let Some(b) = TABLE.with(|t| t.borrow().get(&key).cloned()) {
+        return b
+    }
+    let b = match ranks.lowest() {
+        None => Blocks::empty(),
+        Some(r) => {
+            let mut b = Blocks::new();
+            let n = ranks.get(r);
+            let mut rest = ranks;
+            rest.del(r);
+            b.merge(&blocks(rest, numeric), 0, 0, 0);
+            if n >= 2 {
+                let rest = ranks.sub(Ranks::make_pair(r));
+                b.merge(&blocks(rest, numeric), 1, 0, 0);
+                b.merge(&blocks(rest, numeric), 0, 0, 1);
+            }
+            if n >= 3 {
+                b.merge(&blocks(ranks.sub(Ranks::make_pung(r)), numeric), 0, 1, 0);
+            }
+            if numeric {
+                if ranks.filter_chow().has(r) {
+                    b.merge(&blocks(ranks.sub(Ranks::make_chow(r)), numeric), 0, 1, 0);
+                }
+                if ranks.filter_penryan().has(r) {
+                    let mut rest = ranks;
+                    rest.del(r);
+                    rest.del(Rank::from_id(r.id() + 1));
+                    b.merge(&blocks(rest, numeric), 0, 0, 1);
+                }
+                if ranks.filter_kanchan().has(r) {
+                    let mut rest = ranks;
+                    rest.del(r);
+                    rest.del(Rank::from_id(r.id() + 2));
+                    b.merge(&blocks(rest, numeric), 0, 0, 1);
+                }
+            }
+            b
+        }
+    };
+    TABLE.with(|t| t.borrow_mut().insert(key, b));
+    b
+}
+
+pub fn standard(figures: &Figures, melds: usize) -> isize {
+    let b = Suit::mk_iter().fold(Blocks::empty(), |b, suit|
+        b.combine(&blocks(figures.ranks(suit), suit.is_numeric())));
+    let mut shanten = 8;
+    for p in 0..2 {
+        for m in 0..5 - cmp::min(melds, 4) {
+            let t = (b.0)[p][m] as isize;
+            if t >= 0 {
+                let m = (m + melds) as isize;
+                let t = cmp::min(t, 4 - m);
+                shanten = cmp::min(shanten, 8 - 2 * m - t - p as isize);
+            }
+        }
+    }
+    shanten
+}
+
+pub fn seven_pairs(figures: &Figures) -> isize {
+    let pairs: usize = Suit::mk_iter().map(|s| figures.ranks(s).filter_pair().count()).sum();
+    let kinds: usize = Suit::mk_iter().map(|s| figures.ranks(s).filter_one().count()).sum();
+    6 - pairs as isize + cmp::max(0, 7 - kinds as isize)
+}
+
+pub fn thirteen_orphans(figures: &Figures) -> isize {
+    let orphans = |s: Suit| Ranks::from_raw(figures.ranks(s).raw() & (orphans(s).raw() * 7));
+    let kinds: usize = Suit::mk_iter().map(|s| orphans(s).filter_one().count()).sum();
+    let pair = Suit::mk_iter().any(|s| !orphans(s).filter_pair().is_empty());
+    13 - kinds as isize - pair as isize
+}
+
+pub fn shanten(figures: &Figures, melds: usize) -> isize {
+    let s = standard(figures, melds);
+    if melds == 0 {
+        cmp::min(s, cmp::min(seven_pairs(figures), thirteen_orphans(figures)))
+    } else {
+        s
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Acceptance {
+    pub discard: Figure,
+    pub shanten: isize,
+    pub tiles: Vec<(Figure, usize)>,
+}
+
+impl Acceptance {
+    pub fn count(&self) -> usize {
+        self.tiles.iter().map(|&(_, n)| n).sum()
+    }
+}
+
+pub fn waits(figures: &Figures, melds: usize) -> Vec<Figure> {
+    Figure::mk_iter().filter(|&f| {
+        let mut t = figures.clone();
+        t.add(f);
+        figures.get(f) < 4 && shanten(&t, melds) < 0
+    }).collect()
+}
+
+// `total` holds the copies of each figure in play, so figures taken out of the set accept nothing
+pub fn acceptance(figures: &Figures, melds: usize, visible: &Figures, total: &Figures) -> Vec<Acceptance> {
+    Figure::mk_iter().filter(|&f| figures.get(f) > 0).map(|discard| {
+        let mut rest = figures.clone();
+        rest.del(discard);
+        let s = shanten(&rest, melds);
+        let tiles = Figure::mk_iter().filter_map(|f| {
+            let mut t = rest.clone();
+            t.add(f);
+            if rest.get(f) < total.get(f) && shanten(&t, melds) < s {
+                Some((f, total.get(f) - cmp::min(total.get(f), visible.get(f))))
+            } else {
+                None
+            }
+        }).collect();
+        Acceptance { discard, shanten: s, tiles }
+    }).collect()
+}
+
+#[test]
+fn shanten_test() {
+    let figs = |s: &str| Figures::parse_mpsz(s).unwrap();
+    assert_eq!(shanten(&figs("123m456p789s11222z"), 0), -1);
+    assert_eq!(shanten(&figs("123m456p789s1122z"), 0), 0);
+    assert_eq!(shanten(&figs("1112345678999m"), 0), 0);
+    assert_eq!(shanten(&figs("147m258p369s1234z"), 0), 6);
+    assert_eq!(shanten(&figs("1133m5577p99s116z"), 0), 0);
+    assert_eq!(shanten(&figs("19m19p19s1234567z"), 0), 0);
+    assert_eq!(shanten(&figs("19m19p19s123456z"), 0), 1);
+    assert_eq!(shanten(&figs("2355m"), 3), 0);
+    assert_eq!(shanten(&figs("25m"), 4), 0);
+}
+
+#[test]
+fn acceptance_test() {
+    let figs = |s: &str| Figures::parse_mpsz(s).unwrap();
+    let list = |s: &str| {
+        let mut fs = figs(s);
+        let mut v = Vec::new();
+        while let Some(f) = fs.next() {
+            v.push(f)
+        }
+        v
+    };
+    assert_eq!(waits(&figs("1112345678999m"), 0), list("123456789m"));
+    assert_eq!(waits(&figs("123m456p789s1122z"), 0), list("12z"));
+    let mut full = Figures::new();
+    for f in Figure::mk_iter() {
+        for _ in 0..4 {
+            full.add(f);
+        }
+    }
+    let hand = figs("123m456p789s11226z");
+    let a = acceptance(&hand, 0, &hand, &full);
+    let best = a.iter().find(|a| a.discard == list("6z")[0]).unwrap();
+    assert_eq!(best.shanten, 0);
+    assert_eq!(best.count(), 4);
+    let mut sanma = full.clone();
+    for f in list("2345678m") {
+        for _ in 0..4 {
+            sanma.del(f);
+        }
+    }
+    let hand = figs("13m456p789s112227z");
+    let kanchan = |total: &Figures| acceptance(&hand, 0, &hand, total).into_iter()
+        .find(|a| a.discard == list("7z")[0]).unwrap().count();
+    assert_eq!(kanchan(&full), 4);
+    assert_eq!(kanchan(&sanma), 0);
+}
@@ -1,6 +1,7 @@
 use std;
 use tile::*;
 use meld::*;
+use rule::*;
 use rand;
 use std::mem;
 
@@ -14,10 +15,18 @@ pub struct Table {
     pub rivers: Rivers
 }
 
+// dead wall laid out from `dead`: replacement tiles, then dora and ura-dora indicators in turn
 pub struct Wall {
-    pub tiles: [Tile; Tile::N],
+    pub tiles: [Tile; Tile::N + Tile::N_FLOWERS],
+    pub size: usize,
+    pub dead_wall: usize,
+    pub max_replacements: usize,
     pub index: usize,
-    pub ridge: usize
+    pub ridge: usize,
+    pub dead: usize,
+    pub indicators: usize,
+    pub pending: usize,
+    pub drawn: usize
 }
 
 
@@ -106,10 +115,25 @@ impl Rivers {
     pub fn get(&self, index: usize) -> Option<&DiscardedTile> {
         self.slice().get(index)
     }
+    pub fn declare_riichi(&mut self) {
+        if let Some(d) = self.last_mut() {
+            *d = d.add_riichi_flag();
+        }
+    }
+    pub fn sideways(&self, wind: Wind) -> Option<usize> {
+        let mut declared = false;
+        for (i, d) in self.iter().enumerate().filter(|(_, d)| d.discarded_by() == wind) {
+            declared |= d.is_riichi_declaration();
+            if declared && !d.is_robbed() {
+                return Some(i)
+            }
+        }
+        None
+    }
     pub fn rob(&mut self, wind: Wind) -> Option<(Tile, usize)> {
         let i = self.index-1;
         let d = self.last_mut()?;
-        d.add_robbed_mark(wind);
+        *d = d.add_robbed_mark(wind);
         Some((d.tile(), i))
     }
 }
@@ -161,9 +185,38 @@ impl Melds {
     }
 }
 
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct Winds(u8);
+
+impl Winds {
+    pub fn new() -> Self {
+        Winds(0)
+    }
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    pub fn count(self) -> usize {
+        self.0.count_ones() as usize
+    }
+    pub fn add(&mut self, wind: Wind) {
+        self.0 |= 1 << wind.id()
+    }
+    pub fn contains(self, wind: Wind) -> bool {
+        (self.0 & (1 << wind.id())) != 0
+    }
+    pub fn iter(self) -> impl Iterator<Item=Wind> {
+        Wind::make_iter().filter(move |&w| self.contains(w))
+    }
+    pub fn iter_from(self, from: Wind) -> impl Iterator<Item=Wind> {
+        from.others().filter(move |&w| self.contains(w))
+    }
+}
+
 pub struct Lands {
     pub tiles: [Tiles; Wind::N],
-    pub melds: Melds
+    pub melds: Melds,
+    pub norths: [usize; Wind::N],
+    pub flowers: [Tiles; Wind::N]
 }
 
 
@@ -186,6 +239,16 @@ impl Wind {
         let id = self.id();
         (id+1..id+4).map(Self::from_id)
     }
+    pub fn make_seats(seats: usize) -> impl Iterator<Item=Self> {
+        (0..seats).map(Self::from_id)
+    }
+    pub fn others_of(self, seats: usize) -> impl Iterator<Item=Self> {
+        let id = self.id();
+        (id+1..id+seats).map(move |i| Self::from_id(i % seats))
+    }
+    pub fn next_of(self, seats: usize) -> Self {
+        Self::from_id((self.id() + 1) % seats)
+    }
     pub fn nth(self, offset: usize) -> Self {
         Self::from_id(self.id() + offset)
     }
@@ -198,6 +261,9 @@ impl Wind {
     pub fn leftside(self) -> Self {
         self.nth(3)
     }
+    pub fn figure(self) -> Figure {
+        Figure::from_suitrank(Suit::HOUNOR, Rank::from_id(self.id()))
+    }
     pub fn show(self) -> &'static str {
         match self {
             Self::EAST => "東",
@@ -210,9 +276,13 @@ impl Wind {
 }
 
 impl Table {
-    pub fn new() -> Self {
+    pub fn new(rules: &RuleSet) -> Self {
         Table {
-            wall : Wall::new(),
+            wall : match (rules.style, rules.seats) {
+                (Style::Mcr, _) => Wall::with_flowers(),
+                (_, 3) => Wall::three_player(rules.dead_wall),
+                _ => Wall::new(rules.dead_wall),
+            },
             lands : Lands::new(),
             rivers : Rivers::new()
         }
@@ -223,7 +293,8 @@ impl Table {
         self.wall.shuffle();
     }
     pub fn break_tiles(&mut self, dice: usize) {
-        self.wall.make_break(dice * 32 % Tile::N)
+        let size = self.wall.size;
+        self.wall.make_break(dice * 32 % size)
     }
 
     pub fn draw_tile(&mut self) -> Option<Tile> {
@@ -232,50 +303,104 @@ impl Table {
     pub fn draw_replacement(&mut self) -> Option<Tile> {
         self.wall.next()
     }
+    // sets aside the flowers in a dealt hand, drawing a replacement for each
+    pub fn replace_flowers(&mut self, wind: Wind) {
+        loop {
+            let land = &mut self.lands.tiles[wind.id()];
+            let flower = match land.extract(Figure::FLOWER).or_else(|| land.extract(Figure::SEASON)) {
+                Some(flower) => flower,
+                None => return
+            };
+            self.lands.flowers[wind.id()].add(flower);
+            match self.wall.next() {
+                Some(tile) => self.lands.tiles[wind.id()].add(tile),
+                None => return
+            }
+        }
+    }
 }
 
 impl Wall {
     pub const N_DEAD_WALL: usize = 14;
+    pub const N_REPLACEMENT: usize = 4;
+    pub const N_INDICATOR: usize = 5;
 
-    pub fn new() -> Self {
+    pub fn new(dead_wall: usize) -> Self {
+        Self::from_tiles(Tile::mk_iter(), Self::N_REPLACEMENT, dead_wall)
+    }
+
+    // 2m-8m are removed and the dead wall holds 4 more replacements for north tiles
+    pub fn three_player(dead_wall: usize) -> Self {
+        let removed = |t: &Tile| t.suit() == Suit::CHARA && !t.figure().is_terminal();
+        Self::from_tiles(Tile::mk_iter().filter(|t| !removed(t)), 2 * Self::N_REPLACEMENT, dead_wall)
+    }
+
+    // played to the last tile, replacements for flowers and kongs come from the other end
+    pub fn with_flowers() -> Self {
+        Self::from_tiles(Tile::mk_iter_with_flowers(), 0, 0)
+    }
+
+    fn from_tiles(tiles: impl Iterator<Item=Tile>, max_replacements: usize, dead_wall: usize) -> Self {
         let mut w = Wall {
-            tiles: [Tile::from_id(0); Tile::N],
+            tiles: [Tile::from_id(0); Tile::N + Tile::N_FLOWERS],
+            size: 0,
+            dead_wall,
+            max_replacements,
             index: 0,
             ridge: 0,
+            dead: 0,
+            indicators: 0,
+            pending: 0,
+            drawn: 0,
         };
-        for i in 0..Tile::N {
-            w.tiles[i] = Tile::from_id(i);
+        for t in tiles {
+            w.tiles[w.size] = t;
+            w.size += 1;
         }
         w
     }
 
-    fn add(a: usize, b: usize) -> usize {
-        if a + b >= Tile::N {
-            a + b - Tile::N
-        } else {
-            a + b
-        }
+    fn add(&self, a: usize, b: usize) -> usize {
+        (a + b) % self.size
     }
 
-    fn sub(a: usize, b: usize) -> usize {
-        if a >= b {
-            a - b
-        } else {
-            Tile::N + a - b
-        }
+    fn sub(&self, a: usize, b: usize) -> usize {
+        (self.size + a - b) % self.size
     }
 
     pub fn len(&self) -> usize {
-        if self.index == self.ridge {
-            Tile::N
-        } else {
-            Self::sub(self.index, self.ridge)
+        self.size - self.drawn
+    }
+
+    // shrinks to dead_wall as the extra replacements are drawn
+    pub fn dead_size(&self) -> usize {
+        if self.dead_wall == 0 {
+            return 0
+        }
+        std::cmp::max(self.dead_wall, (self.max_replacements + 2 * Self::N_INDICATOR).saturating_sub(self.replacements()))
+    }
+
+    pub fn live(&self) -> usize {
+        self.len().saturating_sub(self.dead_size())
+    }
+
+    pub fn can_replace(&self) -> bool {
+        self.live() > 0 && (self.dead_wall == 0 || self.replacements() < self.max_replacements)
+    }
+
+    // copies of each figure the wall was built from
+    pub fn figures(&self) -> Figures {
+        let mut figures = Figures::new();
+        for t in self.tiles[..self.size].iter().filter(|t| !t.is_flower()) {
+            figures.add(t.figure());
         }
+        figures
     }
 
     pub fn next_back(&mut self) -> Option<Tile> {
-        if self.len() > Self::N_DEAD_WALL {
-            self.index = Self::sub(self.index, 1);
+        if self.live() > 0 {
+            self.index = self.sub(self.index, 1);
+            self.drawn += 1;
             let i = self.index;
             Some(self.tiles[i])
         } else {
@@ -284,9 +409,10 @@ impl Wall {
     }
 
     pub fn next(&mut self) -> Option<Tile> {
-        if self.len() > Self::N_DEAD_WALL {
+        if self.can_replace() {
             let i = self.ridge;
-            self.ridge = Self::add(self.ridge, 1);
+            self.ridge = self.add(self.ridge, 1);
+            self.drawn += 1;
             Some(self.tiles[i])
         } else {
             None
@@ -294,7 +420,7 @@ impl Wall {
     }
 
     pub fn shuffle(&mut self) {
-        for i in 0..Tile::N{
+        for i in 0..self.size {
             let j = rand::random::<usize>() % (1 + i);
             if i != j {
                 self.tiles.swap(i, j)
@@ -305,6 +431,56 @@ impl Wall {
     pub fn make_break(&mut self, pos: usize) {
         self.index = pos;
         self.ridge = pos;
+        self.dead = pos;
+        self.indicators = (self.dead_wall > 0) as usize;
+        self.pending = 0;
+        self.drawn = 0;
+    }
+
+    pub fn replacements(&self) -> usize {
+        self.sub(self.ridge, self.dead)
+    }
+
+    fn indicator(&self, nth: usize, ura: bool) -> Tile {
+        let offset = self.max_replacements + 2 * nth + ura as usize;
+        self.tiles[self.add(self.dead, offset)]
+    }
+
+    pub fn dora_indicators(&self) -> Vec<Tile> {
+        (0..self.indicators).map(|i| self.indicator(i, false)).collect()
+    }
+
+    pub fn ura_indicators(&self) -> Vec<Tile> {
+        (0..self.indicators).map(|i| self.indicator(i, true)).collect()
+    }
+
+    // without 2m-8m a 1m indicator points to 9m
+    pub fn doras(&self, ura: bool) -> Vec<Figure> {
+        (0..self.indicators).map(|i| {
+            let f = self.indicator(i, ura).figure();
+            if self.size < Tile::N && f.suit() == Suit::CHARA && f.rank().id() == 0 {
+                Figure::from_suitrank(Suit::CHARA, Rank::from_id(8))
+            } else {
+                f.dora()
+            }
+        }).collect()
+    }
+
+    pub fn reveal_dora(&mut self) {
+        if self.dead_wall > 0 && self.indicators < Self::N_INDICATOR {
+            self.indicators += 1;
+        }
+    }
+
+    pub fn defer_dora(&mut self) {
+        self.pending += 1;
+    }
+
+    pub fn flush_dora(&mut self) {
+        while self.pending > 0 {
+            self.pending -= 1;
+            self.reveal_dora();
+        }
     }
 }
 
@@ -313,12 +489,53 @@ impl Lands {
         Lands {
             tiles: [Tiles::new(), Tiles::new(), Tiles::new(), Tiles::new()],
             melds: Melds::new(),
+            norths: [0; Wind::N],
+            flowers: [Tiles::new(), Tiles::new(), Tiles::new(), Tiles::new()],
         }
     }
     pub fn clear(&mut self) {
         for tiles in self.tiles.iter_mut() {
             tiles.clear()
         }
-        self.melds.clear()
+        self.melds.clear();
+        self.norths = [0; Wind::N];
+        for flowers in self.flowers.iter_mut() {
+            flowers.clear()
+        }
     }
-}
\ No newline at end of file
+}
+#[test]
+fn dead_wall_test() {
+    for mut wall in [Wall::new(Wall::N_DEAD_WALL), Wall::three_player(Wall::N_DEAD_WALL), Wall::new(18)] {
+        wall.shuffle();
+        wall.make_break(100 % wall.size);
+        let mut drawn = Vec::new();
+        while let Some(t) = wall.next() {
+            drawn.push(t);
+        }
+        assert_eq!(drawn.len(), wall.max_replacements);
+        while let Some(t) = wall.next_back() {
+            drawn.push(t);
+        }
+        assert_eq!(drawn.len(), wall.size - wall.dead_wall);
+        for _ in 0..Wall::N_INDICATOR {
+            wall.reveal_dora();
+        }
+        for t in wall.dora_indicators().into_iter().chain(wall.ura_indicators()) {
+            assert!(!drawn.contains(&t));
+        }
+    }
+    let sanma = Wall::three_player(Wall::N_DEAD_WALL);
+    assert_eq!(sanma.size, 108);
+    assert_eq!(sanma.max_replacements, 2 * Wall::N_REPLACEMENT);
+    assert_eq!(sanma.figures().get(Figure::from_suitrank(Suit::CHARA, Rank::from_id(0))), 4);
+    assert_eq!(sanma.figures().get(Figure::from_suitrank(Suit::CHARA, Rank::from_id(4))), 0);
+    let mut wall = Wall::with_flowers();
+    wall.make_break(0);
+    assert!(wall.dora_indicators().is_empty());
+    let mut n = 0;
+    while wall.next_back().or_else(|| wall.next()).is_some() {
+        n += 1;
+    }
+    assert_eq!(n, Tile::N + Tile::N_FLOWERS);
+}
@@ -14,15 +14,27 @@ pub struct RankSpec(u8);
 
 impl Tile {
     pub const N: usize = 136;
+    // the four flowers and four seasons follow as the honor ranks 8 and 9
+    pub const N_FLOWERS: usize = 8;
+    const FLOWERS: [&'static str; Self::N_FLOWERS] = ["🀢","🀣","🀤","🀥","🀦","🀧","🀨","🀩"];
     pub fn id(&self) -> usize {
         self.0 as usize
     }
     pub fn from_id(id: usize) -> Self {
-        Tile((id % Self::N) as u8)
+        Tile((id % (Self::N + Self::N_FLOWERS)) as u8)
     }
     pub fn mk_iter() -> impl Iterator<Item=Self> {
         (0..Self::N).map(Self::from_id)
     }
+    pub fn mk_iter_with_flowers() -> impl Iterator<Item=Self> {
+        (0..Self::N + Self::N_FLOWERS).map(Self::from_id)
+    }
+    pub fn is_flower(&self) -> bool {
+        self.figure().is_flower()
+    }
+    pub fn show_flower(&self) -> &'static str {
+        Self::FLOWERS[self.id() - Self::N]
+    }
     pub fn suit(&self) -> Suit {
         self.figure().suit()
     }
@@ -57,16 +69,22 @@ impl RankSpec {
 
 impl Figure {
     pub const N: usize = 34;
-    const STRS: [&'static str; Self::N] = [
+    pub const FLOWER: Self = Figure(34);
+    pub const SEASON: Self = Figure(35);
+    const STRS: [&'static str; Self::N + 2] = [
         "🀇","🀈","🀉","🀊","🀋","🀌","🀍","🀎","🀏",
         "🀐","🀑","🀒","🀓","🀔","🀕","🀖","🀗","🀘",
         "🀙","🀚","🀛","🀜","🀝","🀞","🀟","🀠","🀡",
-        "🀀","🀁","🀂","🀃","🀄","🀅", "🀆"];
+        "🀀","🀁","🀂","🀃","🀄","🀅", "🀆", "🀢", "🀦"];
+    #[cfg(test)]
+    pub fn parse_mpsz(s: &str) -> Self {
+        Figures::parse_mpsz(s).unwrap().next().unwrap()
+    }
     pub fn id(&self) -> usize {
         self.0 as usize
     }
     pub fn from_id(id: usize) -> Self {
-        Figure((id % Self::N) as u8)
+        Figure((id % (Self::N + 2)) as u8)
     }
     pub fn from_suitrank(s: Suit, r: Rank) -> Self {
         Self::from_id(s.id() * 9 + r.id())
@@ -83,6 +101,34 @@ impl Figure {
     pub fn show(&self) -> &'static str {
         Self::STRS[self.id()]
     }
+    pub fn is_honor(&self) -> bool {
+        self.suit().is_horner()
+    }
+    pub fn is_terminal(&self) -> bool {
+        self.suit().is_numeric() && (self.rank().id() == 0 || self.rank().id() == 8)
+    }
+    pub fn is_orphan(&self) -> bool {
+        self.is_honor() || self.is_terminal()
+    }
+    pub fn is_wind(&self) -> bool {
+        self.is_honor() && self.rank().id() < 4
+    }
+    pub fn is_dragon(&self) -> bool {
+        self.is_honor() && (4..7).contains(&self.rank().id())
+    }
+    pub fn is_flower(&self) -> bool {
+        self.is_honor() && self.rank().id() >= 7
+    }
+    pub fn dora(&self) -> Figure {
+        let r = self.rank().id();
+        let next = match r {
+            _ if self.suit().is_numeric() => (r + 1) % 9,
+            0..=3 => (r + 1) % 4,
+            4 => 6,
+            _ => r - 1,
+        };
+        Figure::from_suitrank(self.suit(), Rank::from_id(next))
+    }
     pub fn parse(s: &str) -> Option<Self> {
         Self::STRS.iter()
             .position(|t| t == &s)
@@ -130,6 +176,18 @@ impl Set {
     pub fn shape(&self) -> Shape {
         Shape::from_id(self.raw() >> 6)
     }
+    pub fn has_orphan(&self) -> bool {
+        let figure = self.figure();
+        figure.is_orphan() || (self.shape() == Shape::CHOW && figure.rank().id() == 6)
+    }
+    pub fn ranks(&self) -> Ranks {
+        let rank = self.figure().rank();
+        match self.shape() {
+            Shape::CHOW => Ranks::make_chow(rank),
+            Shape::PUNG => Ranks::make_pung(rank),
+            _ => Ranks::make_kong(rank),
+        }
+    }
 }
 
 impl Suit {
@@ -168,6 +226,49 @@ impl Rank {
     }
 }
 
+// bit 4 * suit + spec marks a red copy of the five
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct RedFives(u16);
+
+impl RedFives {
+    const FIVE: usize = 4;
+    pub fn new() -> Self {
+        RedFives(0)
+    }
+    pub fn add(&mut self, suit: Suit, spec: usize) {
+        if suit.is_numeric() {
+            self.0 |= 1 << (4 * suit.id() + spec);
+        }
+    }
+    pub fn count_in(&self, suit: Suit) -> usize {
+        ((self.0 >> (4 * suit.id())) & 0xf).count_ones() as usize
+    }
+    pub fn mask(&self, suit: Suit) -> u64 {
+        (((self.0 >> (4 * suit.id())) & 0xf) as u64) << (4 * Self::FIVE)
+    }
+    pub fn is_red(&self, tile: Tile) -> bool {
+        tile.suit().is_numeric() && tile.rank().id() == Self::FIVE
+            && (self.0 >> (4 * tile.suit().id() + tile.spec())) & 1 != 0
+    }
+    pub fn count(&self, tiles: &Tiles) -> usize {
+        Suit::mk_iter().map(|s| ((tiles.0)[s.id()].0 & self.mask(s)).count_ones() as usize).sum()
+    }
+    pub fn show(&self, tile: Tile) -> String {
+        if self.is_red(tile) {
+            format!("{}{}", Self::MARK, tile.figure().show())
+        } else {
+            tile.figure().show().to_string()
+        }
+    }
+    pub const MARK: &'static str = "赤";
+    pub fn parse(s: &str) -> Option<(Figure, bool)> {
+        match s.strip_prefix(Self::MARK) {
+            Some(rest) => Figure::parse(rest).map(|f| (f, true)),
+            None => Figure::parse(s).map(|f| (f, false)),
+        }
+    }
+}
+
 #[derive(Clone,Debug,PartialEq,Eq,PartialOrd)]
 pub struct Tiles([RankSpecs; Suit::N]);
 #[derive(Clone,Debug,PartialEq,Eq,PartialOrd)]
@@ -212,11 +313,22 @@ impl RankSpecs {
     pub fn has(&self, rs: RankSpec) -> bool {
         (self.0 & (1 << rs.id())) != 0
     }
+    pub fn extract_prefer(&mut self, r: Rank, prefer: u64) -> Option<RankSpec> {
+        let p = self.0 & (0xf << (4 * r.id()));
+        let p = if p & prefer != 0 { p & prefer } else { p };
+        if p != 0 {
+            let t = p.trailing_zeros();
+            self.0 &= !(1 << t);
+            Some(RankSpec::from_id(t as usize))
+        } else {
+            None
+        }
+    }
     pub fn extract(&mut self, r: Rank) -> Option<RankSpec> {
         let p = self.0 & (0xf << (4 * r.id()));
         if p != 0 {
             let t = p.trailing_zeros();
-            self.0 = p & (p - 1);
+            self.0 &= !(1 << t);
             Some(RankSpec::from_id(t as usize))
         } else {
             None
@@ -264,6 +376,14 @@ impl Tiles {
             Tile::from_suit_rankspec(s, rs)
         )
     }
+    pub fn extract_with(&mut self, figure: Figure, reds: RedFives, red: bool) -> Option<Tile> {
+        let (r, s) = (figure.rank(), figure.suit());
+        let mask = reds.mask(s);
+        let prefer = if red { mask } else { !mask };
+        (self.0)[s.id()].extract_prefer(r, prefer).map(|rs|
+            Tile::from_suit_rankspec(s, rs)
+        )
+    }
     pub fn extract_set(&mut self, set: Set) -> Option<Vec<Tile>> {
         let figure = set.figure();
         let (r, s) = (figure.rank(), figure.suit());
@@ -309,9 +429,48 @@ impl Figures {
         SuitRanks::from_suitranks(suit, (self.0)[suit.id()])
     }
 
+    pub fn ranks(&self, suit: Suit) -> Ranks {
+        (self.0)[suit.id()]
+    }
     pub fn add(&mut self, figure: Figure) {
         (self.0)[figure.suit().id()].add(figure.rank())
     }
+    pub fn del(&mut self, figure: Figure) {
+        (self.0)[figure.suit().id()].del(figure.rank())
+    }
+    pub fn add_set(&mut self, set: Set) {
+        let ranks = &mut (self.0)[set.figure().suit().id()];
+        ranks.0 += set.ranks().0;
+    }
+    pub fn get(&self, figure: Figure) -> usize {
+        (self.0)[figure.suit().id()].get(figure.rank())
+    }
+
+    pub fn parse_mpsz(s: &str) -> Option<Self> {
+        let mut figures = Figures::new();
+        let mut ranks = Vec::new();
+        for c in s.chars() {
+            let suit = match c {
+                '1'..='9' => {
+                    ranks.push(Rank::from_id(c as usize - '1' as usize));
+                    continue
+                },
+                'm' => Suit::CHARA,
+                's' => Suit::BAMBOO,
+                'p' => Suit::CIRCLE,
+                'z' => Suit::HOUNOR,
+                _ => return None
+            };
+            for rank in ranks.drain(..) {
+                figures.add(Figure::from_suitrank(suit, rank));
+            }
+        }
+        if ranks.is_empty() {
+            Some(figures)
+        } else {
+            None
+        }
+    }
 
     pub fn has_chow(&self, rep: Figure) -> bool {
         (self.0)[rep.suit().id()].filter_chow().has(rep.rank())
@@ -363,9 +522,28 @@ impl Ranks {
     pub fn add(&mut self, rank: Rank) {
         self.0 += 1 << (3 * rank.id());
     }
+    pub fn del(&mut self, rank: Rank) {
+        self.0 -= 1 << (3 * rank.id());
+    }
     pub fn has(&self, rank: Rank) -> bool {
         self.0 & (0o7 << (3 * rank.id())) != 0
     }
+    pub fn get(&self, rank: Rank) -> usize {
+        ((self.0 >> (3 * rank.id())) & 0o7) as usize
+    }
+    pub fn lowest(&self) -> Option<Rank> {
+        if self.0 != 0 {
+            Some(Rank::from_id((self.0.trailing_zeros() / 3) as usize))
+        } else {
+            None
+        }
+    }
+    pub fn contains(self, other: Self) -> bool {
+        (0..Rank::N).all(|i| self.get(Rank::from_id(i)) >= other.get(Rank::from_id(i)))
+    }
+    pub fn sub(self, other: Self) -> Self {
+        Ranks(self.0 - other.0)
+    }
     pub fn make_kong(rank: Rank) -> Self {
         Ranks::from_raw(0o4 << (3 * rank.id()))
     }
@@ -373,7 +551,7 @@ impl Ranks {
         Ranks::from_raw(0o3 << (3 * rank.id()))
     }
     pub fn make_chow(rank: Rank) -> Self {
-        Ranks::from_raw(0o111 << (1 * rank.id()))
+        Ranks::from_raw(0o111 << (3 * rank.id()))
     }
     pub fn make_pair(rank: Rank) -> Self {
         Ranks::from_raw(0o2 << (3 * rank.id()))
@@ -507,11 +685,11 @@ impl SuitRanks {
     }
 
     pub fn is_pong(&self) -> bool {
-        self.tile_count() == 3 && !self.ranks().filter_chow().is_empty()
+        self.tile_count() == 3 && !self.ranks().filter_pung().is_empty()
     }
 
     pub fn is_kong(&self) -> bool {
-        self.tile_count() == 3 && !self.ranks().filter_chow().is_empty()
+        self.tile_count() == 4 && !self.ranks().filter_kong().is_empty()
     }
 
     pub fn is_kanchan(&self) -> bool {
@@ -544,3 +722,28 @@ impl SuitRanks {
     }
 }
 
+
+#[test]
+fn dora_test() {
+    assert_eq!(Figure::parse_mpsz("9m").dora(), Figure::parse_mpsz("1m"));
+    assert_eq!(Figure::parse_mpsz("4z").dora(), Figure::parse_mpsz("1z"));
+    assert_eq!(Figure::parse_mpsz("7z").dora(), Figure::parse_mpsz("6z"));
+    assert_eq!(Figure::parse_mpsz("5z").dora(), Figure::parse_mpsz("7z"));
+}
+
+#[test]
+fn red_five_test() {
+    let mut reds = RedFives::new();
+    reds.add(Suit::CHARA, 0);
+    let five = Figure::from_suitrank(Suit::CHARA, Rank::from_id(4));
+    let mut tiles = Tiles::new();
+    for spec in 0..2 {
+        tiles.add(Tile::from_id(4 * five.id() + spec));
+    }
+    assert_eq!(reds.count(&tiles), 1);
+    let t = tiles.clone().extract_with(five, reds, false).unwrap();
+    assert!(!reds.is_red(t));
+    let t = tiles.extract_with(five, reds, true).unwrap();
+    assert!(reds.is_red(t));
+    assert_eq!(RedFives::parse("赤🀋"), Some((five, true)));
+}
@@ -0,0 +1,405 @@
+use tile::*;
+use table::*;
+use hand::*;
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Context {
+    pub seat: Wind,
+    pub round: Wind,
+    pub riichi: bool,
+    pub double_riichi: bool,
+    pub ippatsu: bool,
+    pub tsumo: bool,
+    pub last_tile: bool,
+    pub rinshan: bool,
+    pub chankan: bool,
+    pub first_turn: bool,
+    pub dora: usize,
+    pub ura_dora: usize,
+    pub aka_dora: usize,
+    pub open_tanyao: bool,
+    pub double_yakuman: bool,
+    pub flowers: usize,
+    pub last_copy: bool,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Yaku {
+    Riichi,
+    DoubleRiichi,
+    Ippatsu,
+    MenzenTsumo,
+    Pinfu,
+    Tanyao,
+    Iipeikou,
+    SeatWind,
+    RoundWind,
+    Haku,
+    Hatsu,
+    Chun,
+    Haitei,
+    Houtei,
+    Rinshan,
+    Chankan,
+    Chiitoitsu,
+    Sanshoku,
+    Ittsu,
+    Chanta,
+    Toitoi,
+    Sanankou,
+    Sankantsu,
+    SanshokuDoukou,
+    Shousangen,
+    Honroutou,
+    Honitsu,
+    Junchan,
+    Ryanpeikou,
+    Chinitsu,
+    Kokushi,
+    Suuankou,
+    Daisangen,
+    Shousuushii,
+    Daisuushii,
+    Tsuuiisou,
+    Ryuuiisou,
+    Chinroutou,
+    Chuuren,
+    Suukantsu,
+    Tenhou,
+    Chiihou,
+    Dora,
+    UraDora,
+    AkaDora,
+}
+
+impl Context {
+    pub fn new(seat: Wind, round: Wind) -> Self {
+        Context {
+            seat,
+            round,
+            riichi: false,
+            double_riichi: false,
+            ippatsu: false,
+            tsumo: false,
+            last_tile: false,
+            rinshan: false,
+            chankan: false,
+            first_turn: false,
+            dora: 0,
+            ura_dora: 0,
+            aka_dora: 0,
+            open_tanyao: true,
+            double_yakuman: true,
+            flowers: 0,
+            last_copy: false,
+        }
+    }
+    pub fn is_value(&self, figure: Figure) -> bool {
+        figure.is_dragon() || figure == self.seat.figure() || figure == self.round.figure()
+    }
+}
+
+impl Yaku {
+    pub fn is_yakuman(self) -> bool {
+        use self::Yaku::*;
+        matches!(self,
+            Kokushi | Suuankou | Daisangen | Shousuushii | Daisuushii | Tsuuiisou |
+            Ryuuiisou | Chinroutou | Chuuren | Suukantsu | Tenhou | Chiihou)
+    }
+    pub fn han(self, closed: bool) -> usize {
+        use self::Yaku::*;
+        match self {
+            Riichi | Ippatsu | MenzenTsumo | Pinfu | Iipeikou => if closed { 1 } else { 0 },
+            Tanyao | SeatWind | RoundWind | Haku | Hatsu | Chun |
+            Haitei | Houtei | Rinshan | Chankan | Dora | UraDora | AkaDora => 1,
+            DoubleRiichi | Chiitoitsu => if closed { 2 } else { 0 },
+            Sanshoku | Ittsu | Chanta => if closed { 2 } else { 1 },
+            Toitoi | Sanankou | Sankantsu | SanshokuDoukou | Shousangen | Honroutou => 2,
+            Honitsu | Junchan => if closed { 3 } else { 2 },
+            Ryanpeikou => if closed { 3 } else { 0 },
+            Chinitsu => if closed { 6 } else { 5 },
+            _ => 13,
+        }
+    }
+    pub fn show(self) -> &'static str {
+        use self::Yaku::*;
+        match self {
+            Riichi => "立直",
+            DoubleRiichi => "両立直",
+            Ippatsu => "一発",
+            MenzenTsumo => "門前清自摸和",
+            Pinfu => "平和",
+            Tanyao => "断幺九",
+            Iipeikou => "一盃口",
+            SeatWind => "自風牌",
+            RoundWind => "場風牌",
+            Haku => "白",
+            Hatsu => "發",
+            Chun => "中",
+            Haitei => "海底摸月",
+            Houtei => "河底撈魚",
+            Rinshan => "嶺上開花",
+            Chankan => "槍槓",
+            Chiitoitsu => "七対子",
+            Sanshoku => "三色同順",
+            Ittsu => "一気通貫",
+            Chanta => "混全帯幺九",
+            Toitoi => "対々和",
+            Sanankou => "三暗刻",
+            Sankantsu => "三槓子",
+            SanshokuDoukou => "三色同刻",
+            Shousangen => "小三元",
+            Honroutou => "混老頭",
+            Honitsu => "混一色",
+            Junchan => "純全帯幺九",
+            Ryanpeikou => "二盃口",
+            Chinitsu => "清一色",
+            Kokushi => "国士無双",
+            Suuankou => "四暗刻",
+            Daisangen => "大三元",
+            Shousuushii => "小四喜",
+            Daisuushii => "大四喜",
+            Tsuuiisou => "字一色",
+            Ryuuiisou => "緑一色",
+            Chinroutou => "清老頭",
+            Chuuren => "九蓮宝燈",
+            Suukantsu => "四槓子",
+            Tenhou => "天和",
+            Chiihou => "地和",
+            Dora => "ドラ",
+            UraDora => "裏ドラ",
+            AkaDora => "赤ドラ",
+        }
+    }
+}
+
+// for yakuman the value is the number of yakuman, otherwise the han
+pub fn evaluate(d: &Decomposition, ctx: &Context) -> Vec<(Yaku, usize)> {
+    let v = yakuman(d, ctx);
+    if !v.is_empty() {
+        return v
+    }
+    let closed = d.is_closed();
+    let mut v = Vec::new();
+    let figures = d.figures();
+    let standard = d.form == Form::Standard;
+    let chows: Vec<Figure> = d.sets().filter(|s| s.shape() == Shape::CHOW).map(|s| s.figure()).collect();
+    let pungs: Vec<Figure> = d.sets().filter(|s| s.shape() != Shape::CHOW).map(|s| s.figure()).collect();
+    let kongs = d.sets().filter(|s| s.shape() >= Shape::KONG).count();
+    let concealed_pungs = (0..d.groups.len())
+        .filter(|&i| d.groups[i].set.shape() != Shape::CHOW && d.is_concealed_set(i, ctx.tsumo))
+        .count();
+    let has = |f: &dyn Fn(Figure) -> bool| Figure::mk_iter().any(|x| figures.get(x) > 0 && f(x));
+    let honors = has(&|f| f.is_honor());
+    let terminals = has(&|f| f.is_terminal());
+    let simples = has(&|f| !f.is_orphan());
+    let suits = Suit::mk_iter()
+        .filter(|s| s.is_numeric() && !figures.ranks(*s).is_empty())
+        .count();
+
+    {
+        let mut add = |yaku: Yaku| {
+            let han = yaku.han(closed);
+            if han > 0 {
+                v.push((yaku, han))
+            }
+        };
+        if ctx.double_riichi {
+            add(Yaku::DoubleRiichi)
+        } else if ctx.riichi {
+            add(Yaku::Riichi)
+        }
+        if ctx.ippatsu {
+            add(Yaku::Ippatsu)
+        }
+        if ctx.tsumo {
+            add(Yaku::MenzenTsumo)
+        }
+        if standard && chows.len() == 4 && !ctx.is_value(d.pairs[0]) && d.wait == Wait::Ryanmen {
+            add(Yaku::Pinfu)
+        }
+        if !honors && !terminals && (ctx.open_tanyao || d.is_closed()) {
+            add(Yaku::Tanyao)
+        }
+        let mut sorted = chows.clone();
+        sorted.sort_by_key(|f| f.id());
+        let mut peikou = 0;
+        let mut i = 0;
+        while i + 1 < sorted.len() {
+            if sorted[i] == sorted[i + 1] {
+                peikou += 1;
+                i += 2;
+            } else {
+                i += 1;
+            }
+        }
+        match peikou {
+            1 => add(Yaku::Iipeikou),
+            2 => add(Yaku::Ryanpeikou),
+            _ => {}
+        }
+        for &f in pungs.iter() {
+            if f.is_dragon() {
+                add(match f.rank().id() {
+                    4 => Yaku::Chun,
+                    5 => Yaku::Hatsu,
+                    _ => Yaku::Haku,
+                })
+            }
+            if f == ctx.seat.figure() {
+                add(Yaku::SeatWind)
+            }
+            if f == ctx.round.figure() {
+                add(Yaku::RoundWind)
+            }
+        }
+        // a replacement tile is not the last tile of the live wall
+        if ctx.last_tile && !ctx.rinshan {
+            add(if ctx.tsumo { Yaku::Haitei } else { Yaku::Houtei })
+        }
+        if ctx.rinshan {
+            add(Yaku::Rinshan)
+        }
+        if ctx.chankan {
+            add(Yaku::Chankan)
+        }
+        if d.form == Form::SevenPairs {
+            add(Yaku::Chiitoitsu)
+        }
+        let in_all_suits = |fs: &Vec<Figure>, r: Rank|
+            Suit::mk_iter().filter(|s| s.is_numeric()).all(|s| fs.contains(&Figure::from_suitrank(s, r)));
+        if Rank::mk_iter().any(|r| in_all_suits(&chows, r)) {
+            add(Yaku::Sanshoku)
+        }
+        if Rank::mk_iter().any(|r| in_all_suits(&pungs, r)) {
+            add(Yaku::SanshokuDoukou)
+        }
+        if Suit::mk_iter().filter(|s| s.is_numeric()).any(|s|
+            [0, 3, 6].iter().all(|&r| chows.contains(&Figure::from_suitrank(s, Rank::from_id(r))))) {
+            add(Yaku::Ittsu)
+        }
+        if standard && !chows.is_empty() && d.pairs[0].is_orphan() && d.sets().all(|s| s.has_orphan()) {
+            add(if honors { Yaku::Chanta } else { Yaku::Junchan })
+        }
+        if !simples && honors && terminals {
+            add(Yaku::Honroutou)
+        }
+        if standard && chows.is_empty() {
+            add(Yaku::Toitoi)
+        }
+        if concealed_pungs == 3 {
+            add(Yaku::Sanankou)
+        }
+        if kongs == 3 {
+            add(Yaku::Sankantsu)
+        }
+        if pungs.iter().filter(|f| f.is_dragon()).count() == 2 && d.pairs[0].is_dragon() && standard {
+            add(Yaku::Shousangen)
+        }
+        if suits == 1 {
+            add(if honors { Yaku::Honitsu } else { Yaku::Chinitsu })
+        }
+    }
+    if !v.is_empty() {
+        if ctx.dora > 0 {
+            v.push((Yaku::Dora, ctx.dora))
+        }
+        if ctx.ura_dora > 0 {
+            v.push((Yaku::UraDora, ctx.ura_dora))
+        }
+        if ctx.aka_dora > 0 {
+            v.push((Yaku::AkaDora, ctx.aka_dora))
+        }
+    }
+    v
+}
+
+fn yakuman(d: &Decomposition, ctx: &Context) -> Vec<(Yaku, usize)> {
+    let mut v = Vec::new();
+    let figures = d.figures();
+    let standard = d.form == Form::Standard;
+    let pungs: Vec<Figure> = d.sets().filter(|s| s.shape() != Shape::CHOW).map(|s| s.figure()).collect();
+    let concealed_pungs = (0..d.groups.len())
+        .filter(|&i| d.groups[i].set.shape() != Shape::CHOW && d.is_concealed_set(i, ctx.tsumo))
+        .count();
+    let all = |f: &dyn Fn(Figure) -> bool| Figure::mk_iter().all(|x| figures.get(x) == 0 || f(x));
+    let double = if ctx.double_yakuman { 2 } else { 1 };
+    if d.form == Form::ThirteenOrphans {
+        v.push((Yaku::Kokushi, if d.pairs[0] == d.winning { double } else { 1 }))
+    }
+    if standard && concealed_pungs == 4 {
+        v.push((Yaku::Suuankou, if d.wait == Wait::Tanki { double } else { 1 }))
+    }
+    if pungs.iter().filter(|f| f.is_dragon()).count() == 3 {
+        v.push((Yaku::Daisangen, 1))
+    }
+    match pungs.iter().filter(|f| f.is_wind()).count() {
+        4 => v.push((Yaku::Daisuushii, double)),
+        3 if d.pairs[0].is_wind() => v.push((Yaku::Shousuushii, 1)),
+        _ => {}
+    }
+    if all(&|f| f.is_honor()) {
+        v.push((Yaku::Tsuuiisou, 1))
+    }
+    let green = Figures::parse_mpsz("23468s6z").expect("green figures");
+    if all(&|f| green.get(f) > 0) {
+        v.push((Yaku::Ryuuiisou, 1))
+    }
+    if all(&|f| f.is_terminal()) {
+        v.push((Yaku::Chinroutou, 1))
+    }
+    if d.is_closed() && standard {
+        let nine_gates = Ranks::from_raw(0o311111113);
+        let suit = d.winning.suit();
+        let ranks = figures.ranks(suit);
+        if suit.is_numeric() && ranks.count() == 14 && ranks.contains(nine_gates) {
+            v.push((Yaku::Chuuren, if ranks.sub(nine_gates) == Ranks::make_one(d.winning.rank()) { 2 } else { 1 }))
+        }
+    }
+    if d.sets().filter(|s| s.shape() >= Shape::KONG).count() == 4 {
+        v.push((Yaku::Suukantsu, 1))
+    }
+    if ctx.first_turn && ctx.tsumo {
+        v.push((if ctx.seat == Wind::EAST { Yaku::Tenhou } else { Yaku::Chiihou }, 1))
+    }
+    v
+}
+
+pub fn han(yakus: &[(Yaku, usize)]) -> usize {
+    yakus.iter().filter(|(y, _)| !y.is_yakuman()).map(|&(_, h)| h).sum()
+}
+
+pub fn yakuman_count(yakus: &[(Yaku, usize)]) -> usize {
+    yakus.iter().filter(|(y, _)| y.is_yakuman()).map(|&(_, n)| n).sum()
+}
+
+#[test]
+fn yaku_test() {
+    let best = |h: &str, w: &str, ctx: &Context| Hand::parse_mpsz(h).decompose(Figure::parse_mpsz(w)).iter()
+        .map(|d| evaluate(d, ctx))
+        .max_by_key(|y| (yakuman_count(y), han(y)))
+        .unwrap();
+    let ron = Context::new(Wind::SOUTH, Wind::EAST);
+    let y = best("234567m23455p23s", "4s", &ron);
+    assert!(y.contains(&(Yaku::Pinfu, 1)));
+    assert!(y.contains(&(Yaku::Tanyao, 1)));
+    assert!(y.contains(&(Yaku::Sanshoku, 2)));
+    let y = best("1112345678999m", "5m", &ron);
+    assert_eq!(y, vec![(Yaku::Chuuren, 2)]);
+    let y = best("112233m445566p7z", "7z", &ron);
+    assert!(y.contains(&(Yaku::Ryanpeikou, 3)));
+    let y = best("111m999p11s11z222z", "1s", &ron);
+    assert!(y.contains(&(Yaku::Honroutou, 2)));
+    assert!(y.contains(&(Yaku::Toitoi, 2)));
+    assert!(y.contains(&(Yaku::Sanankou, 2)));
+    assert!(y.contains(&(Yaku::SeatWind, 1)));
+    let y = best("9m111222333444z", "9m", &ron);
+    assert!(y.contains(&(Yaku::Daisuushii, 2)));
+    let y = best("9m111222333444z", "9m", &Context { double_yakuman: false, ..ron });
+    assert!(y.contains(&(Yaku::Daisuushii, 1)));
+    let last = Context { tsumo: true, last_tile: true, ..ron };
+    assert!(best("234567m23455p23s", "4s", &last).contains(&(Yaku::Haitei, 1)));
+    let y = best("234567m23455p23s", "4s", &Context { rinshan: true, ..last });
+    assert!(y.contains(&(Yaku::Rinshan, 1)));
+    assert!(!y.contains(&(Yaku::Haitei, 1)));
+}